@@ -0,0 +1,206 @@
+//! Animated GIF of a game, one frame per move
+
+use super::{Coord, FirError, FirGame, Order, RuleSet, Theme};
+
+// palette indices, in the order of `palette`
+const BOARD: u8 = 0;
+const LINE: u8 = 1;
+const BLACK: u8 = 2;
+const WHITE: u8 = 3;
+const HIGHLIGHT: u8 = 4;
+
+/// how a game is animated
+#[derive(Copy, Clone, Debug)]
+pub struct GifOptions {
+    /// distance between two lines in pixels
+    pub cell: u32,
+    /// time each move is shown, in milliseconds
+    pub delay_ms: u32,
+    /// time the final position is shown before looping
+    pub final_delay_ms: u32,
+    pub theme: Theme,
+}
+
+impl Default for GifOptions {
+    fn default() -> Self {
+        GifOptions {
+            cell: 24,
+            delay_ms: 500,
+            final_delay_ms: 3000,
+            theme: Theme::default(),
+        }
+    }
+}
+
+fn palette(theme: &Theme) -> Vec<u8> {
+    [
+        theme.board,
+        theme.line,
+        theme.black,
+        theme.white,
+        theme.highlight,
+    ]
+    .concat()
+}
+
+/// encode the game given by `moves` as a looping GIF
+///
+/// the first frame is the empty board and every move adds a frame. the last
+/// move is dotted and a finished game shows its winning line.
+pub fn game_to_gif(
+    size: usize,
+    rule: RuleSet,
+    moves: &[Coord],
+    options: &GifOptions,
+) -> Result<Vec<u8>, FirError> {
+    let encoding = |e: gif::EncodingError| FirError::Encoding(e.to_string());
+    let cell = options.cell.max(8);
+    let width = (cell * (size as u32 + 1)) as u16;
+
+    let mut out = vec![];
+    {
+        let mut encoder = gif::Encoder::new(&mut out, width, width, &palette(&options.theme))
+            .map_err(encoding)?;
+        encoder
+            .set_repeat(gif::Repeat::Infinite)
+            .map_err(encoding)?;
+
+        let mut game = FirGame::with_rule(size, rule);
+        for i in 0..=moves.len() {
+            if i > 0 {
+                let coord = moves[i - 1];
+                game.play(coord.x, coord.y, game.order)?;
+            }
+            let delay = if i == moves.len() {
+                options.final_delay_ms
+            } else {
+                options.delay_ms
+            };
+            let frame = gif::Frame {
+                width,
+                height: width,
+                // gif delays are in hundredths of a second
+                delay: (delay / 10).min(u16::MAX as u32) as u16,
+                buffer: game.raster(cell).into(),
+                ..gif::Frame::default()
+            };
+            encoder.write_frame(&frame).map_err(encoding)?;
+        }
+    }
+    Ok(out)
+}
+
+impl FirGame {
+    /// board as palette indices, row by row from the top left pixel
+    fn raster(&self, cell: u32) -> Vec<u8> {
+        let size = self.size() as u32;
+        let width = cell * (size + 1);
+        let mut pixels = vec![BOARD; (width * width) as usize];
+        let center = |c: Coord| {
+            (
+                (cell + c.x * cell) as i64,
+                (cell + (size - 1 - c.y) * cell) as i64,
+            )
+        };
+        let mut put = |x: i64, y: i64, color: u8| {
+            if x >= 0 && y >= 0 && x < width as i64 && y < width as i64 {
+                pixels[(y * width as i64 + x) as usize] = color;
+            }
+        };
+
+        let (start, end) = (cell as i64, (cell * size) as i64);
+        for k in 0..size as i64 {
+            let p = cell as i64 * (k + 1);
+            for q in start..=end {
+                put(q, p, LINE);
+                put(p, q, LINE);
+            }
+        }
+
+        let radius = (cell * 9 / 20) as i64;
+        for coord in self.moves() {
+            let (cx, cy) = center(*coord);
+            let fill = match self.stone_at(*coord) {
+                Some(Order::Black) => BLACK,
+                _ => WHITE,
+            };
+            for dy in -radius..=radius {
+                for dx in -radius..=radius {
+                    let d = dx * dx + dy * dy;
+                    if d <= radius * radius {
+                        // outline so white stones stand out on light boards
+                        let edge = d > (radius - 1) * (radius - 1);
+                        put(cx + dx, cy + dy, if edge { LINE } else { fill });
+                    }
+                }
+            }
+        }
+
+        if let Some(last) = self.moves().last() {
+            let (cx, cy) = center(*last);
+            let dot = (radius / 3).max(1);
+            for dy in -dot..=dot {
+                for dx in -dot..=dot {
+                    if dx * dx + dy * dy <= dot * dot {
+                        put(cx + dx, cy + dy, HIGHLIGHT);
+                    }
+                }
+            }
+        }
+
+        if let Some((_, line)) = self.winning_line() {
+            let (x1, y1) = center(line[0]);
+            let (x2, y2) = center(line[line.len() - 1]);
+            let half = (cell as i64 / 12).max(1);
+            let steps = (x2 - x1).abs().max((y2 - y1).abs()).max(1);
+            for s in 0..=steps {
+                let x = x1 + (x2 - x1) * s / steps;
+                let y = y1 + (y2 - y1) * s / steps;
+                for dy in -half..=half {
+                    for dx in -half..=half {
+                        put(x + dx, y + dy, HIGHLIGHT);
+                    }
+                }
+            }
+        }
+
+        pixels
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn one_frame_per_move_and_the_empty_board() {
+        let moves: Vec<Coord> = [(3, 3), (4, 4), (3, 4), (4, 3)]
+            .iter()
+            .map(|&(x, y)| Coord { x, y })
+            .collect();
+        let options = GifOptions {
+            cell: 10,
+            delay_ms: 200,
+            final_delay_ms: 1000,
+            ..GifOptions::default()
+        };
+        let gif = game_to_gif(8, RuleSet::Freestyle, &moves, &options).unwrap();
+
+        let mut decoder = gif::DecodeOptions::new().read_info(gif.as_slice()).unwrap();
+        assert_eq!((decoder.width(), decoder.height()), (90, 90));
+        let mut delays = vec![];
+        while let Some(frame) = decoder.read_next_frame().unwrap() {
+            delays.push(frame.delay);
+        }
+        assert_eq!(delays.len(), moves.len() + 1);
+        assert_eq!(delays[0], 20);
+        assert_eq!(delays[moves.len()], 100);
+    }
+
+    #[test]
+    fn illegal_move_is_an_error() {
+        let moves = [Coord { x: 3, y: 3 }, Coord { x: 3, y: 3 }];
+        let options = GifOptions::default();
+        assert!(game_to_gif(8, RuleSet::Freestyle, &moves, &options).is_err());
+    }
+}
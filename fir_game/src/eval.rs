@@ -0,0 +1,222 @@
+//! Position evaluation and move suggestions
+
+use super::{rule, Coord, FirGame, Order, SqaureState};
+
+/// score of a won position, shorter wins score higher
+pub const WIN_SCORE: i32 = 1_000_000;
+
+/// how many candidate moves are searched on each ply
+const SEARCH_WIDTH: usize = 12;
+
+/// one suggested move and the line expected to follow it
+#[derive(Clone, Debug, PartialEq)]
+pub struct Suggestion {
+    pub coord: Coord,
+    /// score after this move, from the point of view of the side to move
+    pub score: i32,
+    /// principal variation, starting with `coord`
+    pub pv: Vec<Coord>,
+}
+
+/// result of [FirGame::evaluate]
+#[derive(Clone, Debug, PartialEq)]
+pub struct Evaluation {
+    /// score of the position for the side to move
+    pub score: i32,
+    /// best moves first
+    pub suggestions: Vec<Suggestion>,
+}
+
+impl Evaluation {
+    /// moves until a forced win (positive) or loss (negative), if the score is one
+    pub fn mate_in(&self) -> Option<i32> {
+        if self.score.abs() < WIN_SCORE - 1000 {
+            return None;
+        }
+        let plies = WIN_SCORE - self.score.abs();
+        Some(self.score.signum() * (plies + 1) / 2)
+    }
+}
+
+impl FirGame {
+    /// search `depth` plies and return the score with the `top_n` best moves
+    pub fn evaluate(&self, depth: u32, top_n: usize) -> Evaluation {
+        let depth = depth.max(1);
+        let mut suggestions: Vec<Suggestion> = self
+            .candidate_moves(2)
+            .into_iter()
+            .take(SEARCH_WIDTH)
+            .map(|coord| {
+                let (score, mut pv) = match self.after(coord) {
+                    Some(next) if next.is_end().0 => (WIN_SCORE - 1, vec![]),
+                    Some(next) => {
+                        let (score, pv) = next.negamax(depth - 1, -WIN_SCORE, WIN_SCORE, 2);
+                        (-score, pv)
+                    }
+                    None => (-WIN_SCORE, vec![]),
+                };
+                pv.insert(0, coord);
+                Suggestion { coord, score, pv }
+            })
+            .collect();
+        suggestions.sort_by_key(|s| std::cmp::Reverse(s.score));
+
+        let score = match suggestions.first() {
+            Some(best) => best.score,
+            None if self.is_end().0 => -WIN_SCORE,
+            None => 0,
+        };
+        suggestions.truncate(top_n);
+        Evaluation { score, suggestions }
+    }
+
+    /// static score of the position for the side to move
+    pub fn static_score(&self) -> i32 {
+        let own = self.line_score(self.order);
+        let opp = self.line_score(self.order.opponent());
+        // having the move is worth about half a tempo
+        own + own / 4 - opp
+    }
+
+    /// copy of the game after the side to move plays `coord`
+    pub(crate) fn after(&self, coord: Coord) -> Option<FirGame> {
+        let mut next = self.clone();
+        next.play(coord.x, coord.y, self.order).ok()?;
+        Some(next)
+    }
+
+    fn negamax(&self, depth: u32, mut alpha: i32, beta: i32, ply: i32) -> (i32, Vec<Coord>) {
+        if depth == 0 {
+            return (self.static_score(), vec![]);
+        }
+        let moves = self.candidate_moves(2);
+        if moves.is_empty() {
+            return (0, vec![]);
+        }
+
+        let mut best = (-WIN_SCORE, vec![]);
+        for coord in moves.into_iter().take(SEARCH_WIDTH) {
+            let Some(next) = self.after(coord) else {
+                continue;
+            };
+            let (score, mut pv) = if next.is_end().0 {
+                (WIN_SCORE - ply, vec![])
+            } else {
+                let (score, pv) = next.negamax(depth - 1, -beta, -alpha, ply + 1);
+                (-score, pv)
+            };
+            if score > best.0 || best.1.is_empty() {
+                pv.insert(0, coord);
+                best = (score, pv);
+            }
+            alpha = alpha.max(score);
+            if alpha >= beta {
+                break;
+            }
+        }
+        best
+    }
+
+    /// sum of pattern scores of every line owned by `order`
+    fn line_score(&self, order: Order) -> i32 {
+        let stone: SqaureState = order.into();
+        let size = self.state.get_size();
+        let mut total = 0;
+        for y in 0..size.y as i32 {
+            for x in 0..size.x as i32 {
+                if self.state.at(x, y) != Some(stone) {
+                    continue;
+                }
+                for dir in rule::DIRECTIONS {
+                    if self.state.at(x - dir.0, y - dir.1) == Some(stone) {
+                        continue;
+                    }
+                    let len = self.state.run_length(x, y, dir, stone) as i32;
+                    let open = [(x - dir.0, y - dir.1), (x + len * dir.0, y + len * dir.1)]
+                        .iter()
+                        .filter(|(ex, ey)| self.state.at(*ex, *ey) == Some(SqaureState::Empty))
+                        .count();
+                    total += pattern_score(len, open);
+                }
+            }
+        }
+        total
+    }
+}
+
+fn pattern_score(len: i32, open: usize) -> i32 {
+    match (len, open) {
+        (5.., _) => WIN_SCORE / 10,
+        (4, 2) => 50_000,
+        (4, 1) | (3, 2) => 5_000,
+        (3, 1) => 500,
+        (2, 2) => 300,
+        (2, 1) => 50,
+        (1, 2) => 10,
+        _ => 0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::RuleSet;
+
+    /// 15 x 15 freestyle game after `moves`, black first
+    fn game(moves: &[(u32, u32)]) -> FirGame {
+        let moves: Vec<Coord> = moves.iter().map(|&(x, y)| Coord { x, y }).collect();
+        FirGame::from_moves(15, RuleSet::Freestyle, &moves).unwrap()
+    }
+
+    #[test]
+    fn finds_immediate_win() {
+        let game = game(&[
+            (3, 7),
+            (0, 0),
+            (4, 7),
+            (0, 2),
+            (5, 7),
+            (0, 4),
+            (6, 7),
+            (0, 6),
+        ]);
+        let evaluation = game.evaluate(2, 3);
+        let best = &evaluation.suggestions[0];
+        assert!([Coord { x: 2, y: 7 }, Coord { x: 7, y: 7 }].contains(&best.coord));
+        assert_eq!(evaluation.score, WIN_SCORE - 1);
+        assert_eq!(evaluation.mate_in(), Some(1));
+    }
+
+    #[test]
+    fn blocks_a_four() {
+        let game = game(&[(3, 7), (2, 7), (4, 7), (0, 0), (5, 7), (0, 2), (6, 7)]);
+        let evaluation = game.evaluate(2, 3);
+        assert_eq!(evaluation.suggestions[0].coord, Coord { x: 7, y: 7 });
+        assert!(evaluation.mate_in().is_none());
+    }
+
+    #[test]
+    fn open_four_is_lost() {
+        let game = game(&[(3, 7), (0, 0), (4, 7), (0, 2), (5, 7), (0, 4), (6, 7)]);
+        let evaluation = game.evaluate(2, 3);
+        assert!(evaluation.mate_in().is_some_and(|moves| moves < 0));
+    }
+
+    #[test]
+    fn finished_game_has_no_suggestions() {
+        let game = game(&[
+            (3, 7),
+            (0, 0),
+            (4, 7),
+            (0, 2),
+            (5, 7),
+            (0, 4),
+            (6, 7),
+            (0, 6),
+            (7, 7),
+        ]);
+        let evaluation = game.evaluate(2, 3);
+        assert!(evaluation.suggestions.is_empty());
+        assert_eq!(evaluation.score, -WIN_SCORE);
+    }
+}
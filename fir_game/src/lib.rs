@@ -1,3 +1,22 @@
+mod animation;
+mod eval;
+mod puzzle;
+mod record;
+mod render;
+mod renlib;
+mod rule;
+mod sgf;
+
+pub use animation::{game_to_gif, GifOptions};
+pub use error::FirError;
+pub use eval::{Evaluation, Suggestion, WIN_SCORE};
+pub use puzzle::{Puzzle, PuzzleGenerator, PuzzleKind};
+pub use record::GameRecord;
+pub use render::{column_name, SvgOptions, Theme};
+pub use renlib::{LibNode, RenLib, RENLIB_SIZE};
+pub use rule::RuleSet;
+pub use sgf::{SgfGame, SgfNode};
+
 mod error {
     #[derive(thiserror::Error, Debug)]
     pub enum FirError {
@@ -5,10 +24,16 @@ mod error {
         InvalidIndexAccessInBoard,
         #[error("target square already used")]
         TargetSquareAlreadyUsed,
+        #[error("move is forbidden by the rule set")]
+        ForbiddenMove,
+        #[error("invalid record: {0}")]
+        InvalidRecord(String),
+        #[error("image encoding failed: {0}")]
+        Encoding(String),
     }
 }
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
 pub struct Coord {
     pub x: u32,
     pub y: u32,
@@ -46,6 +71,25 @@ pub enum Order {
     White,
 }
 
+impl Order {
+    /// the other side
+    pub fn opponent(self) -> Order {
+        match self {
+            Order::Black => Order::White,
+            Order::White => Order::Black,
+        }
+    }
+}
+
+impl From<Order> for SqaureState {
+    fn from(value: Order) -> Self {
+        match value {
+            Order::Black => SqaureState::Black,
+            Order::White => SqaureState::White,
+        }
+    }
+}
+
 #[derive(Clone, Debug)]
 struct FirGameState {
     size: FirBoardSize,
@@ -66,7 +110,7 @@ impl FirGameState {
 
     pub fn get_square(&self, x: usize, y: usize) -> Result<SqaureState, error::FirError> {
         // -> Result<>
-        if x < self.size.x && y <= self.size.y {
+        if x < self.size.x && y < self.size.y {
             Ok(self.board[y * self.size.x + x])
         } else {
             Err(error::FirError::InvalidIndexAccessInBoard)
@@ -78,14 +122,11 @@ impl FirGameState {
     }
 
     pub fn set_square(&mut self, x: usize, y: usize, order: Order) -> Result<(), error::FirError> {
-        if x < self.size.x && y <= self.size.y {
+        if x < self.size.x && y < self.size.y {
             let s = self.board[self.to1d(x, y)];
             if let SqaureState::Empty = s {
                 let idx = self.to1d(x, y);
-                self.board[idx] = match order {
-                    Order::Black => SqaureState::Black,
-                    Order::White => SqaureState::White,
-                };
+                self.board[idx] = order.into();
                 Ok(())
             } else {
                 Err(error::FirError::TargetSquareAlreadyUsed)
@@ -97,10 +138,12 @@ impl FirGameState {
 }
 
 /// Game
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct FirGame {
     state: FirGameState,
     order: Order,
+    rule: RuleSet,
+    moves: Vec<Coord>,
 }
 
 #[derive(Default)]
@@ -112,33 +155,177 @@ pub enum Response {
     Draw,
 }
 
+impl Default for FirGame {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl FirGame {
     pub fn new() -> Self {
+        FirGame::with_rule(8, RuleSet::Freestyle)
+    }
+
+    /// make a `size` x `size` game played with `rule`
+    pub fn with_rule(size: usize, rule: RuleSet) -> Self {
         FirGame {
-            state: FirGameState::empty_board(FirBoardSize::sqaure(8)),
+            state: FirGameState::empty_board(FirBoardSize::sqaure(size)),
             order: Order::Black,
+            rule,
+            moves: vec![],
         }
     }
 
+    /// replay `moves` from the empty board, black first
+    pub fn from_moves(size: usize, rule: RuleSet, moves: &[Coord]) -> Result<Self, FirError> {
+        let mut game = FirGame::with_rule(size, rule);
+        for coord in moves {
+            game.play(coord.x, coord.y, game.order)?;
+        }
+        Ok(game)
+    }
+
+    /// width of the (square) board
+    pub fn size(&self) -> usize {
+        self.state.get_size().x
+    }
+
+    /// stone on `coord`, `None` for an empty or outside point
+    pub fn stone_at(&self, coord: Coord) -> Option<Order> {
+        match self.state.get_square(coord.x as usize, coord.y as usize) {
+            Ok(SqaureState::Black) => Some(Order::Black),
+            Ok(SqaureState::White) => Some(Order::White),
+            _ => None,
+        }
+    }
+
+    /// moves played so far, black first
+    pub fn moves(&self) -> &[Coord] {
+        &self.moves
+    }
+
+    pub fn rule(&self) -> RuleSet {
+        self.rule
+    }
+
+    /// side to move
+    pub fn order(&self) -> Order {
+        self.order
+    }
+
     pub fn play(&mut self, x: u32, y: u32, order: Order) -> Result<Response, error::FirError> {
         if order != self.order {
             return Ok(Response::OnGoing);
         }
+        if self.state.get_square(x as usize, y as usize)? == SqaureState::Empty
+            && self
+                .state
+                .is_forbidden(x as usize, y as usize, self.order, self.rule)
+        {
+            return Err(error::FirError::ForbiddenMove);
+        }
         self.state.set_square(x as usize, y as usize, self.order)?;
-        self.order = match self.order {
-            Order::Black => Order::White,
-            Order::White => Order::Black,
-        };
+        self.moves.push(Coord { x, y });
+        self.order = self.order.opponent();
         Ok(Response::OnGoing)
     }
 
+    /// every point the side to move may play on
+    ///
+    /// occupied points and moves prohibited by the rule set are left out.
+    /// a finished game has no legal moves.
+    pub fn legal_moves(&self) -> Vec<Coord> {
+        if self.is_end().0 {
+            return vec![];
+        }
+        let size = self.state.get_size();
+        let mut moves = vec![];
+        for y in 0..size.y {
+            for x in 0..size.x {
+                if self.is_legal(x, y) {
+                    moves.push(Coord {
+                        x: x as u32,
+                        y: y as u32,
+                    });
+                }
+            }
+        }
+        moves
+    }
+
+    /// legal points within `radius` of an existing stone, most promising first
+    ///
+    /// points are ordered by a cheap heuristic that favours extending own
+    /// lines and blocking the opponent's. on an empty board only the center
+    /// is returned.
+    pub fn candidate_moves(&self, radius: u32) -> Vec<Coord> {
+        if self.is_end().0 {
+            return vec![];
+        }
+        let size = self.state.get_size();
+        if self.state.board.iter().all(|s| *s == SqaureState::Empty) {
+            return vec![Coord {
+                x: (size.x / 2) as u32,
+                y: (size.y / 2) as u32,
+            }];
+        }
+
+        let r = radius as i32;
+        let mut scored = vec![];
+        for y in 0..size.y {
+            for x in 0..size.x {
+                let (sx, sy) = (x as i32, y as i32);
+                let near = (-r..=r).any(|dy| {
+                    (-r..=r).any(|dx| {
+                        matches!(
+                            self.state.at(sx + dx, sy + dy),
+                            Some(SqaureState::Black) | Some(SqaureState::White)
+                        )
+                    })
+                });
+                if near && self.is_legal(x, y) {
+                    scored.push((
+                        self.move_score(sx, sy),
+                        Coord {
+                            x: x as u32,
+                            y: y as u32,
+                        },
+                    ));
+                }
+            }
+        }
+        scored.sort_by_key(|(score, _)| std::cmp::Reverse(*score));
+        scored.into_iter().map(|(_, coord)| coord).collect()
+    }
+
+    fn is_legal(&self, x: usize, y: usize) -> bool {
+        matches!(self.state.get_square(x, y), Ok(SqaureState::Empty))
+            && !self.state.is_forbidden(x, y, self.order, self.rule)
+    }
+
+    /// sum of squared run lengths both sides would get on (x, y)
+    fn move_score(&self, x: i32, y: i32) -> u32 {
+        let own: SqaureState = self.order.into();
+        let opp: SqaureState = self.order.opponent().into();
+        rule::DIRECTIONS
+            .iter()
+            .map(|dir| {
+                let mine = self.state.run_length(x, y, *dir, own) as u32;
+                let theirs = self.state.run_length(x, y, *dir, opp) as u32;
+                // attacking is worth slightly more than defending
+                mine * mine * 2 + theirs * theirs
+            })
+            .sum()
+    }
+
+    /// plain board, one row per line from the top row down
     pub fn board_state(&self) -> String {
         let mut str = String::new();
         let size = self.state.get_size();
-        for i in 0..size.x {
+        for y in (0..size.y).rev() {
             str.push('\n');
-            for j in 0..size.y {
-                let can = match self.state.get_square(i, j).unwrap() {
+            for x in 0..size.x {
+                let can = match self.state.get_square(x, y).unwrap() {
                     SqaureState::Empty => '*',
                     SqaureState::Black => 'X',
                     SqaureState::White => '0',
@@ -149,87 +336,43 @@ impl FirGame {
         str
     }
 
+    /// check whether someone has a winning line under the rule set
     pub fn is_end(&self) -> (bool, Order) {
-        for i in 0..8 {
-            for j in 0..3 {
-                let mut wstate = true;
-                let mut bstate = true;
-                for k in 0..5 {
-                    if self.state.get_square(i, j + k).unwrap() != SqaureState::White {
-                        wstate = false;
-                    }
-                    if self.state.get_square(i, j + k).unwrap() != SqaureState::Black {
-                        bstate = false;
-                    }
-                }
-                if wstate {
-                    return (true, Order::White);
-                } else if bstate {
-                    return (true, Order::Black);
-                }
-            }
-        }
-
-        for i in 0..3 {
-            for j in 0..8 {
-                let mut wstate = true;
-                let mut bstate = true;
-                for k in 0..5 {
-                    if self.state.get_square(i + k, j).unwrap() != SqaureState::White {
-                        wstate = false;
-                    }
-                    if self.state.get_square(i + k, j).unwrap() != SqaureState::Black {
-                        bstate = false;
-                    }
-                }
-                if wstate {
-                    return (true, Order::White);
-                } else if bstate {
-                    return (true, Order::Black);
-                }
-            }
-        }
-
-        for i in 0..3 {
-            for j in 0..3 {
-                let mut wstate = true;
-                let mut bstate = true;
-                for k in 0..5 {
-                    if self.state.get_square(i + k, j + k).unwrap() != SqaureState::White {
-                        wstate = false;
-                    }
-                    if self.state.get_square(i + k, j + k).unwrap() != SqaureState::Black {
-                        bstate = false;
-                    }
-                }
-                if wstate {
-                    return (true, Order::White);
-                } else if bstate {
-                    return (true, Order::Black);
-                }
-            }
+        match self.winning_line() {
+            Some((order, _)) => (true, order),
+            None => (false, Order::White),
         }
+    }
 
-        for i in 5..8 {
-            for j in 0..3 {
-                let mut wstate = true;
-                let mut bstate = true;
-                for k in 0..5 {
-                    if self.state.get_square(i - k, j + k).unwrap() != SqaureState::White {
-                        wstate = false;
+    /// winner and the stones of its winning line
+    pub fn winning_line(&self) -> Option<(Order, Vec<Coord>)> {
+        let size = self.state.get_size();
+        for y in 0..size.y as i32 {
+            for x in 0..size.x as i32 {
+                let order = match self.state.at(x, y) {
+                    Some(SqaureState::Black) => Order::Black,
+                    Some(SqaureState::White) => Order::White,
+                    _ => continue,
+                };
+                for dir in rule::DIRECTIONS {
+                    // only look at each run once, from its first stone
+                    if self.state.at(x - dir.0, y - dir.1) == Some(order.into()) {
+                        continue;
                     }
-                    if self.state.get_square(i - k, j + k).unwrap() != SqaureState::Black {
-                        bstate = false;
+                    let len = self.state.run_length(x, y, dir, order.into());
+                    if self.rule.is_winning_run(len, order) {
+                        let line = (0..len as i32)
+                            .map(|k| Coord {
+                                x: (x + k * dir.0) as u32,
+                                y: (y + k * dir.1) as u32,
+                            })
+                            .collect();
+                        return Some((order, line));
                     }
                 }
-                if wstate {
-                    return (true, Order::White);
-                } else if bstate {
-                    return (true, Order::Black);
-                }
             }
         }
 
-        (false, Order::White)
+        None
     }
 }
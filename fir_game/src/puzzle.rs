@@ -0,0 +1,483 @@
+//! Puzzles built from forced wins
+//!
+//! A position is a puzzle when the side to move has exactly one first move
+//! that wins by continuous fours (VCF) or by fours and threes (VCT) within a
+//! given number of its own moves.
+
+use super::{rule, Coord, FirError, FirGame, Order, RuleSet, SqaureState};
+use std::fmt;
+use std::str::FromStr;
+
+/// which threats the attacker may use
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum PuzzleKind {
+    /// victory by continuous fours
+    Vcf,
+    /// victory by continuous threes and fours
+    Vct,
+}
+
+impl fmt::Display for PuzzleKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            PuzzleKind::Vcf => "vcf",
+            PuzzleKind::Vct => "vct",
+        })
+    }
+}
+
+impl FromStr for PuzzleKind {
+    type Err = FirError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "vcf" => Ok(PuzzleKind::Vcf),
+            "vct" => Ok(PuzzleKind::Vct),
+            _ => Err(FirError::InvalidRecord(format!("unknown puzzle kind {s}"))),
+        }
+    }
+}
+
+/// a position with a unique forced win
+///
+/// written as one line, `size;rule;kind;difficulty;setup;solution`, where
+/// moves are comma separated points like `h8` (column letter, row number).
+#[derive(Clone, Debug, PartialEq)]
+pub struct Puzzle {
+    pub size: usize,
+    pub rule: RuleSet,
+    pub kind: PuzzleKind,
+    /// 1 (easy) to 10 (hard)
+    pub difficulty: u32,
+    /// moves leading to the position, black first
+    pub setup: Vec<Coord>,
+    /// main line of the solution, attacker first
+    pub solution: Vec<Coord>,
+}
+
+impl Puzzle {
+    /// widest board the notation can write, one letter a column
+    pub const MAX_SIZE: usize = 26;
+
+    /// the position to solve
+    pub fn position(&self) -> Result<FirGame, FirError> {
+        FirGame::from_moves(self.size, self.rule, &self.setup)
+    }
+
+    /// side that has the forced win
+    pub fn attacker(&self) -> Order {
+        if self.setup.len().is_multiple_of(2) {
+            Order::Black
+        } else {
+            Order::White
+        }
+    }
+
+    /// number of attacker moves in the solution
+    pub fn length(&self) -> usize {
+        self.solution.len().div_ceil(2)
+    }
+}
+
+fn write_moves(f: &mut fmt::Formatter<'_>, moves: &[Coord]) -> fmt::Result {
+    for (i, coord) in moves.iter().enumerate() {
+        if i > 0 {
+            f.write_str(",")?;
+        }
+        write!(f, "{}{}", (b'a' + coord.x as u8) as char, coord.y + 1)?;
+    }
+    Ok(())
+}
+
+/// read moves written by [write_moves], each inside a `size` board
+fn read_moves(s: &str, size: usize) -> Result<Vec<Coord>, FirError> {
+    s.split(',')
+        .filter(|m| !m.is_empty())
+        .map(|m| {
+            let mut chars = m.chars();
+            let column = chars.next().filter(|c| c.is_ascii_lowercase());
+            let row = chars.as_str().parse::<u32>().ok().filter(|r| *r > 0);
+            match (column, row) {
+                (Some(column), Some(row))
+                    if (column as usize - 'a' as usize) < size && row as usize <= size =>
+                {
+                    Ok(Coord {
+                        x: column as u32 - 'a' as u32,
+                        y: row - 1,
+                    })
+                }
+                _ => Err(FirError::InvalidRecord(format!("bad move {m}"))),
+            }
+        })
+        .collect()
+}
+
+impl fmt::Display for Puzzle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{};{};{};{};",
+            self.size, self.rule, self.kind, self.difficulty
+        )?;
+        write_moves(f, &self.setup)?;
+        f.write_str(";")?;
+        write_moves(f, &self.solution)
+    }
+}
+
+impl FromStr for Puzzle {
+    type Err = FirError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let fields: Vec<&str> = s.trim().split(';').collect();
+        let [size, rule, kind, difficulty, setup, solution] = fields[..] else {
+            return Err(FirError::InvalidRecord(format!("bad puzzle {s}")));
+        };
+        let number = |v: &str| {
+            v.parse::<usize>()
+                .map_err(|_| FirError::InvalidRecord(format!("bad number {v}")))
+        };
+        let size = number(size)?;
+        if size == 0 || size > Puzzle::MAX_SIZE {
+            return Err(FirError::InvalidRecord(format!("bad board size {size}")));
+        }
+        Ok(Puzzle {
+            size,
+            rule: rule.parse()?,
+            kind: kind.parse()?,
+            difficulty: number(difficulty)? as u32,
+            setup: read_moves(setup, size)?,
+            solution: read_moves(solution, size)?,
+        })
+    }
+}
+
+/// finds puzzles in games
+#[derive(Copy, Clone, Debug)]
+pub struct PuzzleGenerator {
+    pub kind: PuzzleKind,
+    /// shortest solution worth a puzzle, in attacker moves
+    pub min_length: u32,
+    /// longest solution searched, in attacker moves
+    pub max_length: u32,
+}
+
+impl PuzzleGenerator {
+    /// longest search allowed, the search grows exponentially with it
+    pub const MAX_LENGTH: u32 = 6;
+
+    /// generator searching up to `max_length`, at most [Self::MAX_LENGTH]
+    pub fn new(kind: PuzzleKind, max_length: u32) -> Self {
+        PuzzleGenerator {
+            kind,
+            min_length: 2,
+            max_length: max_length.min(Self::MAX_LENGTH),
+        }
+    }
+
+    /// check one position, given by the moves leading to it
+    ///
+    /// boards wider than [Puzzle::MAX_SIZE] cannot be written as puzzles.
+    pub fn check(&self, size: usize, rule: RuleSet, setup: &[Coord]) -> Option<Puzzle> {
+        if size > Puzzle::MAX_SIZE {
+            return None;
+        }
+        let game = FirGame::from_moves(size, rule, setup).ok()?;
+        // skip positions that are already decided or where the defender attacks
+        if game.is_end().0
+            || !game.winning_points(game.order).is_empty()
+            || !game.winning_points(game.order.opponent()).is_empty()
+        {
+            return None;
+        }
+
+        // every first move must be tried to make sure the win is unique
+        let roots = game.threat_moves(self.kind);
+        let mut solution = None;
+        for coord in roots.iter() {
+            if let Some(line) = game.win_after(*coord, self.kind, self.max_length) {
+                if solution.is_some() {
+                    return None;
+                }
+                solution = Some(line);
+            }
+        }
+        let solution = solution?;
+
+        let puzzle = Puzzle {
+            size,
+            rule,
+            kind: self.kind,
+            difficulty: 0,
+            setup: setup.to_vec(),
+            solution,
+        };
+        if (puzzle.length() as u32) < self.min_length {
+            return None;
+        }
+        Some(Puzzle {
+            difficulty: self.rate(&puzzle, roots.len()),
+            ..puzzle
+        })
+    }
+
+    /// collect puzzles from every position of a game
+    ///
+    /// once a puzzle is found, positions inside its solution are skipped so
+    /// one winning attack gives one puzzle.
+    pub fn scan_game(&self, size: usize, rule: RuleSet, moves: &[Coord]) -> Vec<Puzzle> {
+        let mut puzzles = vec![];
+        let mut ply = 0;
+        while ply < moves.len() {
+            match self.check(size, rule, &moves[..ply]) {
+                Some(puzzle) => {
+                    ply += puzzle.solution.len().max(1);
+                    puzzles.push(puzzle);
+                }
+                None => ply += 1,
+            }
+        }
+        puzzles
+    }
+
+    /// longer lines, threes and many tempting alternatives make it harder
+    fn rate(&self, puzzle: &Puzzle, threats: usize) -> u32 {
+        let kind = match self.kind {
+            PuzzleKind::Vcf => 0,
+            PuzzleKind::Vct => 2,
+        };
+        let distractors = threats.saturating_sub(1) / 4;
+        (puzzle.length() + kind + distractors).clamp(1, 10) as u32
+    }
+}
+
+impl FirGame {
+    /// empty points where `order` would complete a winning line
+    fn winning_points(&self, order: Order) -> Vec<Coord> {
+        let stone: SqaureState = order.into();
+        let size = self.state.get_size();
+        let mut points = vec![];
+        for y in 0..size.y as i32 {
+            for x in 0..size.x as i32 {
+                if self.state.at(x, y) != Some(SqaureState::Empty) {
+                    continue;
+                }
+                // run_length counts the empty point as if the stone was there
+                let wins = rule::DIRECTIONS.iter().any(|dir| {
+                    self.rule
+                        .is_winning_run(self.state.run_length(x, y, *dir, stone), order)
+                });
+                if wins {
+                    points.push(Coord {
+                        x: x as u32,
+                        y: y as u32,
+                    });
+                }
+            }
+        }
+        points
+    }
+
+    /// legal moves of the side to move that make a four, or a three for VCT
+    fn threat_moves(&self, kind: PuzzleKind) -> Vec<Coord> {
+        self.candidate_moves(2)
+            .into_iter()
+            .filter(|coord| {
+                let Some(next) = self.after(*coord) else {
+                    return false;
+                };
+                !next.winning_points(self.order).is_empty()
+                    || kind == PuzzleKind::Vct && next.has_three(*coord, self.order)
+            })
+            .collect()
+    }
+
+    /// can `order` make two winning points at once with a move in line with `coord`
+    fn has_three(&self, coord: Coord, order: Order) -> bool {
+        let mut next = self.clone();
+        next.order = order;
+        line_points(self, coord).into_iter().any(|point| {
+            next.after(point)
+                .is_some_and(|after| after.winning_points(order).len() >= 2)
+        })
+    }
+
+    /// winning line starting with `coord` for the side to move, if every defence fails
+    fn win_after(&self, coord: Coord, kind: PuzzleKind, depth: u32) -> Option<Vec<Coord>> {
+        let attacker = self.order;
+        let next = self.after(coord)?;
+        if next.is_end().0 {
+            return Some(vec![coord]);
+        }
+        if depth <= 1 {
+            return None;
+        }
+
+        let mut defences = next.winning_points(attacker);
+        defences.extend(next.winning_points(next.order));
+        if next.winning_points(attacker).is_empty() {
+            // a three can be blocked on its line or answered with a four
+            defences.extend(line_points(&next, coord));
+            defences.extend(next.threat_moves(PuzzleKind::Vcf));
+        }
+        defences.sort_by_key(|c| (c.y, c.x));
+        defences.dedup();
+
+        let mut main: Option<Vec<Coord>> = None;
+        for defence in defences {
+            // a forbidden point is no defence
+            let Some(after) = next.after(defence) else {
+                continue;
+            };
+            if after.is_end().0 {
+                return None;
+            }
+            let line = after.forced_win(kind, depth - 1)?;
+            // the most stubborn defence is the main line
+            if main.as_ref().is_none_or(|m| line.len() > m.len()) {
+                main = Some([vec![coord, defence], line].concat());
+            }
+        }
+        main
+    }
+
+    /// winning line for the side to move within `depth` of its moves
+    fn forced_win(&self, kind: PuzzleKind, depth: u32) -> Option<Vec<Coord>> {
+        if let Some(coord) = self.winning_points(self.order).first() {
+            return Some(vec![*coord]);
+        }
+        let blocks = self.winning_points(self.order.opponent());
+        let moves = match blocks.as_slice() {
+            [] => self.threat_moves(kind),
+            // a forced block keeps the attack only if it is a four
+            [block] => self
+                .threat_moves(PuzzleKind::Vcf)
+                .into_iter()
+                .filter(|c| c == block)
+                .collect(),
+            _ => return None,
+        };
+        moves
+            .into_iter()
+            .find_map(|coord| self.win_after(coord, kind, depth))
+    }
+}
+
+/// empty points within five squares of `coord` on its four lines
+fn line_points(game: &FirGame, coord: Coord) -> Vec<Coord> {
+    let (x, y) = (coord.x as i32, coord.y as i32);
+    let mut points = vec![];
+    for dir in rule::DIRECTIONS {
+        for k in (-5..=5).filter(|k| *k != 0) {
+            let (px, py) = (x + k * dir.0, y + k * dir.1);
+            if game.state.at(px, py) == Some(SqaureState::Empty) {
+                points.push(Coord {
+                    x: px as u32,
+                    y: py as u32,
+                });
+            }
+        }
+    }
+    points
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn coords(moves: &[(u32, u32)]) -> Vec<Coord> {
+        moves.iter().map(|&(x, y)| Coord { x, y }).collect()
+    }
+
+    /// black to move wins with a double four at (8, 7)
+    fn double_four_setup() -> Vec<Coord> {
+        coords(&[
+            (5, 7),
+            (4, 7),
+            (6, 7),
+            (8, 3),
+            (7, 7),
+            (0, 0),
+            (8, 4),
+            (0, 2),
+            (8, 5),
+            (14, 0),
+            (8, 6),
+            (14, 2),
+        ])
+    }
+
+    #[test]
+    fn check_finds_unique_win() {
+        let generator = PuzzleGenerator::new(PuzzleKind::Vcf, 3);
+        let puzzle = generator
+            .check(15, RuleSet::Freestyle, &double_four_setup())
+            .unwrap();
+        assert_eq!(puzzle.solution[0], Coord { x: 8, y: 7 });
+        assert_eq!(puzzle.attacker(), Order::Black);
+        assert_eq!(puzzle.length(), 2);
+        assert!((1..=10).contains(&puzzle.difficulty));
+    }
+
+    #[test]
+    fn check_skips_positions_without_win() {
+        let generator = PuzzleGenerator::new(PuzzleKind::Vcf, 3);
+        let setup = double_four_setup();
+        assert!(generator
+            .check(15, RuleSet::Freestyle, &setup[..setup.len() - 2])
+            .is_none());
+        // a five to complete is already decided
+        let mut five = setup.clone();
+        five.extend(coords(&[(8, 7), (9, 7)]));
+        assert!(generator.check(15, RuleSet::Freestyle, &five).is_none());
+    }
+
+    #[test]
+    fn scan_game_finds_one_puzzle_per_attack() {
+        let mut moves = double_four_setup();
+        moves.extend(coords(&[(8, 7), (9, 7), (8, 8)]));
+        let generator = PuzzleGenerator::new(PuzzleKind::Vcf, 3);
+        let puzzles = generator.scan_game(15, RuleSet::Freestyle, &moves);
+        assert_eq!(puzzles.len(), 1);
+        assert_eq!(puzzles[0].setup, double_four_setup());
+    }
+
+    #[test]
+    fn puzzle_line_round_trip() {
+        let puzzle = Puzzle {
+            size: 15,
+            rule: RuleSet::Renju,
+            kind: PuzzleKind::Vct,
+            difficulty: 4,
+            setup: coords(&[(7, 7), (7, 8)]),
+            solution: coords(&[(8, 8), (9, 9), (6, 6)]),
+        };
+        let line = puzzle.to_string();
+        assert_eq!(line, "15;renju;vct;4;h8,h9;i9,j10,g7");
+        assert_eq!(line.parse::<Puzzle>().unwrap(), puzzle);
+    }
+
+    #[test]
+    fn moves_outside_the_board_are_rejected() {
+        assert!("8;freestyle;vcf;1;z9;a1".parse::<Puzzle>().is_err());
+        assert!("8;freestyle;vcf;1;a9;a1".parse::<Puzzle>().is_err());
+        assert!("8;freestyle;vcf;1;h8;a1".parse::<Puzzle>().is_ok());
+        assert!("8;freestyle;vcf;1;a0;a1".parse::<Puzzle>().is_err());
+    }
+
+    #[test]
+    fn boards_wider_than_the_alphabet_are_rejected() {
+        assert!("27;freestyle;vcf;1;a1;b1".parse::<Puzzle>().is_err());
+        assert!("0;freestyle;vcf;1;;".parse::<Puzzle>().is_err());
+        let generator = PuzzleGenerator::new(PuzzleKind::Vcf, 3);
+        assert!(generator
+            .check(27, RuleSet::Freestyle, &double_four_setup())
+            .is_none());
+    }
+
+    #[test]
+    fn search_length_is_capped() {
+        let generator = PuzzleGenerator::new(PuzzleKind::Vct, 1000);
+        assert_eq!(generator.max_length, PuzzleGenerator::MAX_LENGTH);
+    }
+}
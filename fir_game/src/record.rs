@@ -0,0 +1,154 @@
+//! Game records and the Piskvork `.psq` format
+//!
+//! A psq file starts with a header such as `Piskvorky 20x20, 11:11, 0`,
+//! followed by one `x,y,time` line per move with 1-based coordinates and the
+//! thinking time in milliseconds. Engine or player names and a closing `-1`
+//! may follow the moves.
+
+use super::{Coord, FirError, FirGame, RuleSet};
+use std::fmt::Write;
+
+/// a played game as a plain list of moves
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct GameRecord {
+    pub size: usize,
+    pub rule: RuleSet,
+    /// black first
+    pub moves: Vec<Coord>,
+    /// time spent on each move in milliseconds, when it is known
+    pub times_ms: Vec<u32>,
+    pub black: Option<String>,
+    pub white: Option<String>,
+}
+
+impl GameRecord {
+    pub fn new(size: usize, rule: RuleSet, moves: Vec<Coord>) -> Self {
+        GameRecord {
+            size,
+            rule,
+            moves,
+            ..GameRecord::default()
+        }
+    }
+
+    /// rebuild the final position
+    pub fn replay(&self) -> Result<FirGame, FirError> {
+        FirGame::from_moves(self.size, self.rule, &self.moves)
+    }
+
+    /// read a psq file
+    pub fn from_psq(text: &str) -> Result<Self, FirError> {
+        let invalid = |msg: &str| FirError::InvalidRecord(format!("psq: {msg}"));
+        let mut lines = text.lines().map(str::trim).filter(|l| !l.is_empty());
+
+        let header = lines.next().ok_or_else(|| invalid("empty file"))?;
+        let size = header
+            .split_whitespace()
+            .nth(1)
+            .and_then(|dim| dim.trim_end_matches(',').split('x').next())
+            .and_then(|w| w.parse::<usize>().ok())
+            .filter(|w| *w > 0)
+            .ok_or_else(|| invalid("bad header"))?;
+
+        let mut record = GameRecord::new(size, RuleSet::Freestyle, vec![]);
+        let mut rest = vec![];
+        for line in lines.by_ref() {
+            let fields: Vec<&str> = line.split(',').map(str::trim).collect();
+            let numbers: Vec<u32> = fields.iter().filter_map(|f| f.parse().ok()).collect();
+            if fields.len() != 3 || numbers.len() != 3 {
+                rest.push(line);
+                break;
+            }
+            let (x, y) = (numbers[0], numbers[1]);
+            if x == 0 || y == 0 || x as usize > size || y as usize > size {
+                return Err(invalid(&format!("move {line} is outside of the board")));
+            }
+            record.moves.push(Coord { x: x - 1, y: y - 1 });
+            record.times_ms.push(numbers[2]);
+        }
+        rest.extend(lines);
+
+        // names come after the moves, anything numeric is trailer info
+        let mut names = rest
+            .into_iter()
+            .filter(|l| l.parse::<i64>().is_err())
+            .map(String::from);
+        record.black = names.next();
+        record.white = names.next();
+        Ok(record)
+    }
+
+    /// write a psq file
+    pub fn to_psq(&self) -> String {
+        let mut psq = String::new();
+        writeln!(psq, "Piskvorky {0}x{0}, 11:11, 0", self.size).unwrap();
+        for (i, coord) in self.moves.iter().enumerate() {
+            let time = self.times_ms.get(i).copied().unwrap_or(0);
+            writeln!(psq, "{},{},{}", coord.x + 1, coord.y + 1, time).unwrap();
+        }
+        if let (Some(black), Some(white)) = (&self.black, &self.white) {
+            writeln!(psq, "{black}").unwrap();
+            writeln!(psq, "{white}").unwrap();
+        }
+        psq.push_str("-1\n");
+        psq
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record() -> GameRecord {
+        let moves = [(7, 7), (8, 8), (0, 14)]
+            .iter()
+            .map(|&(x, y)| Coord { x, y })
+            .collect();
+        GameRecord {
+            times_ms: vec![1200, 0, 350],
+            black: Some(String::from("alice")),
+            white: Some(String::from("bob")),
+            ..GameRecord::new(15, RuleSet::Freestyle, moves)
+        }
+    }
+
+    #[test]
+    fn psq_round_trip() {
+        let record = record();
+        let psq = record.to_psq();
+        assert!(psq.starts_with("Piskvorky 15x15, 11:11, 0\n8,8,1200\n"));
+        assert_eq!(GameRecord::from_psq(&psq).unwrap(), record);
+    }
+
+    #[test]
+    fn psq_round_trip_without_names() {
+        let record = GameRecord {
+            black: None,
+            white: None,
+            ..record()
+        };
+        assert_eq!(GameRecord::from_psq(&record.to_psq()).unwrap(), record);
+    }
+
+    #[test]
+    fn read_psq_with_engine_trailer() {
+        let psq = "Piskvorky 20x20, 11:11, 0\r\n10,10,0\r\n11,11,16\r\npbrain-a.exe\r\npbrain-b.exe\r\n-1\r\n";
+        let record = GameRecord::from_psq(psq).unwrap();
+        assert_eq!(record.size, 20);
+        assert_eq!(
+            record.moves,
+            vec![Coord { x: 9, y: 9 }, Coord { x: 10, y: 10 }]
+        );
+        assert_eq!(record.times_ms, vec![0, 16]);
+        assert_eq!(record.black.as_deref(), Some("pbrain-a.exe"));
+        assert_eq!(record.white.as_deref(), Some("pbrain-b.exe"));
+    }
+
+    #[test]
+    fn reject_broken_psq() {
+        assert!(GameRecord::from_psq("").is_err());
+        assert!(GameRecord::from_psq("Piskvorky, 11:11, 0\n").is_err());
+        assert!(GameRecord::from_psq("Piskvorky 15x15, 11:11, 0\n16,1,0\n").is_err());
+        assert!(GameRecord::from_psq("Piskvorky 15x15, 11:11, 0\n0,1,0\n").is_err());
+    }
+}
@@ -0,0 +1,260 @@
+//! Board rendering for terminals and images
+//!
+//! Columns are labeled with letters from `A` and rows with numbers from `1`
+//! at the bottom, the same points as `h8` in puzzle records.
+
+use super::{Coord, FirGame, Order};
+use std::fmt::Write;
+
+/// colors of a board picture as RGB
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Theme {
+    pub board: [u8; 3],
+    pub line: [u8; 3],
+    pub black: [u8; 3],
+    pub white: [u8; 3],
+    /// last move and winning line
+    pub highlight: [u8; 3],
+}
+
+impl Theme {
+    /// wooden board
+    pub fn wood() -> Self {
+        Theme {
+            board: [220, 179, 92],
+            line: [60, 40, 20],
+            black: [20, 20, 20],
+            white: [245, 245, 245],
+            highlight: [220, 40, 40],
+        }
+    }
+
+    /// black and white print
+    pub fn paper() -> Self {
+        Theme {
+            board: [255, 255, 255],
+            line: [0, 0, 0],
+            black: [0, 0, 0],
+            white: [255, 255, 255],
+            highlight: [0, 90, 200],
+        }
+    }
+
+    /// theme by name, `wood` or `paper`
+    pub fn named(name: &str) -> Option<Self> {
+        match name {
+            "wood" => Some(Theme::wood()),
+            "paper" => Some(Theme::paper()),
+            _ => None,
+        }
+    }
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Theme::wood()
+    }
+}
+
+/// what to draw in an SVG board
+#[derive(Copy, Clone, Debug)]
+pub struct SvgOptions {
+    /// distance between two lines in pixels
+    pub cell: u32,
+    pub coordinates: bool,
+    pub move_numbers: bool,
+    pub highlight_last: bool,
+    pub highlight_win: bool,
+    pub theme: Theme,
+}
+
+impl Default for SvgOptions {
+    fn default() -> Self {
+        SvgOptions {
+            cell: 32,
+            coordinates: true,
+            move_numbers: true,
+            highlight_last: true,
+            highlight_win: true,
+            theme: Theme::default(),
+        }
+    }
+}
+
+/// column label, `A` for the first column
+pub fn column_name(x: u32) -> char {
+    (b'A' + x as u8) as char
+}
+
+fn hex(color: [u8; 3]) -> String {
+    format!("#{:02x}{:02x}{:02x}", color[0], color[1], color[2])
+}
+
+impl FirGame {
+    /// labeled board drawn with box characters, the last move is circled
+    pub fn to_unicode(&self) -> String {
+        let size = self.size();
+        let last = self.moves().last().copied();
+        let header: String = (0..size as u32)
+            .map(|x| format!("{} ", column_name(x)))
+            .collect();
+
+        let mut str = String::new();
+        writeln!(str, "   {}", header.trim_end()).unwrap();
+        for y in (0..size).rev() {
+            write!(str, "{:>2} ", y + 1).unwrap();
+            for x in 0..size {
+                let coord = Coord {
+                    x: x as u32,
+                    y: y as u32,
+                };
+                let glyph = match (self.stone_at(coord), Some(coord) == last) {
+                    (Some(Order::Black), false) => '●',
+                    (Some(Order::White), false) => '○',
+                    (Some(Order::Black), true) => '◉',
+                    (Some(Order::White), true) => '◎',
+                    (None, _) => grid_glyph(x, y, size),
+                };
+                str.push(glyph);
+                if x + 1 < size {
+                    str.push('─');
+                }
+            }
+            writeln!(str, " {}", y + 1).unwrap();
+        }
+        writeln!(str, "   {}", header.trim_end()).unwrap();
+        str
+    }
+
+    /// standalone SVG image of the board
+    pub fn to_svg(&self, options: &SvgOptions) -> String {
+        let size = self.size() as u32;
+        let cell = options.cell.max(8);
+        let width = cell * (size + 1);
+        let theme = options.theme;
+        // center of a point, row 1 at the bottom
+        let center = |c: Coord| (cell + c.x * cell, cell + (size - 1 - c.y) * cell);
+
+        let mut svg = String::new();
+        writeln!(
+            svg,
+            r#"<svg xmlns="http://www.w3.org/2000/svg" width="{width}" height="{width}" viewBox="0 0 {width} {width}">"#
+        )
+        .unwrap();
+        writeln!(
+            svg,
+            r#"<rect width="{width}" height="{width}" fill="{}"/>"#,
+            hex(theme.board)
+        )
+        .unwrap();
+
+        let (start, end) = (cell, cell * size);
+        for k in 0..size {
+            let p = cell + k * cell;
+            writeln!(
+                svg,
+                r#"<line x1="{start}" y1="{p}" x2="{end}" y2="{p}" stroke="{0}"/><line x1="{p}" y1="{start}" x2="{p}" y2="{end}" stroke="{0}"/>"#,
+                hex(theme.line)
+            )
+            .unwrap();
+        }
+
+        if options.coordinates {
+            let font = cell * 2 / 5;
+            for k in 0..size {
+                let p = cell + k * cell;
+                writeln!(
+                    svg,
+                    r#"<text x="{p}" y="{}" font-size="{font}" font-family="sans-serif" text-anchor="middle" fill="{}">{}</text>"#,
+                    width - cell / 4,
+                    hex(theme.line),
+                    column_name(k)
+                )
+                .unwrap();
+                writeln!(
+                    svg,
+                    r#"<text x="{}" y="{}" font-size="{font}" font-family="sans-serif" text-anchor="middle" dominant-baseline="central" fill="{}">{}</text>"#,
+                    cell / 2 - cell / 8,
+                    p,
+                    hex(theme.line),
+                    size - k
+                )
+                .unwrap();
+            }
+        }
+
+        for (i, coord) in self.moves().iter().enumerate() {
+            let (cx, cy) = center(*coord);
+            let (fill, ink) = match self.stone_at(*coord) {
+                Some(Order::Black) => (theme.black, theme.white),
+                _ => (theme.white, theme.black),
+            };
+            writeln!(
+                svg,
+                r#"<circle cx="{cx}" cy="{cy}" r="{}" fill="{}" stroke="{}"/>"#,
+                cell * 9 / 20,
+                hex(fill),
+                hex(theme.line)
+            )
+            .unwrap();
+            if options.move_numbers {
+                writeln!(
+                    svg,
+                    r#"<text x="{cx}" y="{cy}" font-size="{}" font-family="sans-serif" text-anchor="middle" dominant-baseline="central" fill="{}">{}</text>"#,
+                    cell * 2 / 5,
+                    hex(ink),
+                    i + 1
+                )
+                .unwrap();
+            }
+        }
+
+        if options.highlight_last {
+            if let Some(last) = self.moves().last() {
+                let (cx, cy) = center(*last);
+                writeln!(
+                    svg,
+                    r#"<circle cx="{cx}" cy="{cy}" r="{}" fill="none" stroke="{}" stroke-width="2"/>"#,
+                    cell * 9 / 20,
+                    hex(theme.highlight)
+                )
+                .unwrap();
+            }
+        }
+
+        if options.highlight_win {
+            if let Some((_, line)) = self.winning_line() {
+                let (x1, y1) = center(line[0]);
+                let (x2, y2) = center(line[line.len() - 1]);
+                writeln!(
+                    svg,
+                    r#"<line x1="{x1}" y1="{y1}" x2="{x2}" y2="{y2}" stroke="{}" stroke-width="{}" stroke-linecap="round" opacity="0.8"/>"#,
+                    hex(theme.highlight),
+                    (cell / 8).max(2)
+                )
+                .unwrap();
+            }
+        }
+
+        svg.push_str("</svg>\n");
+        svg
+    }
+}
+
+fn grid_glyph(x: usize, y: usize, size: usize) -> char {
+    let top = y + 1 == size;
+    let bottom = y == 0;
+    let left = x == 0;
+    let right = x + 1 == size;
+    match (top, bottom, left, right) {
+        (true, _, true, _) => '┌',
+        (true, _, _, true) => '┐',
+        (true, _, _, _) => '┬',
+        (_, true, true, _) => '└',
+        (_, true, _, true) => '┘',
+        (_, true, _, _) => '┴',
+        (_, _, true, _) => '├',
+        (_, _, _, true) => '┤',
+        _ => '┼',
+    }
+}
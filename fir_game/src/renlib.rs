@@ -0,0 +1,290 @@
+//! RenLib `.lib` opening databases
+//!
+//! A file is a 20 byte header (`0xff "RenLib" 0xff`, major and minor version
+//! and padding) followed by the move tree in pre-order, two bytes a node:
+//! the point and a set of flags. The point byte holds the row in its high
+//! nibble and the column plus one in its low nibble, `0` is no move. A
+//! comment, when flagged, follows its node as zero terminated text padded
+//! to an even length.
+
+use super::{Coord, FirError};
+
+const MAGIC: [u8; 8] = [0xff, b'R', b'e', b'n', b'L', b'i', b'b', 0xff];
+const HEADER_LEN: usize = 20;
+/// RenLib boards are always 15 x 15
+pub const RENLIB_SIZE: usize = 15;
+
+// node flags
+const DOWN: u8 = 0x80;
+const RIGHT: u8 = 0x40;
+const OLD_COMMENT: u8 = 0x20;
+const MARK: u8 = 0x10;
+const COMMENT: u8 = 0x08;
+const START: u8 = 0x04;
+const NO_MOVE: u8 = 0x02;
+const EXTENSION: u8 = 0x01;
+
+/// one move of the library tree
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct LibNode {
+    /// `None` for a node without a move, such as the root
+    pub coord: Option<Coord>,
+    /// first line of the comment
+    pub title: Option<String>,
+    pub comment: Option<String>,
+    pub marked: bool,
+    /// position the library opens on
+    pub start: bool,
+    pub children: Vec<LibNode>,
+}
+
+impl LibNode {
+    /// follow `moves` from this node
+    pub fn find(&self, moves: &[Coord]) -> Option<&LibNode> {
+        let mut node = self;
+        for coord in moves {
+            node = node.children.iter().find(|c| c.coord == Some(*coord))?;
+        }
+        Some(node)
+    }
+
+    /// number of nodes below this one
+    pub fn count(&self) -> usize {
+        self.children.iter().map(|c| 1 + c.count()).sum()
+    }
+}
+
+/// an opening library loaded in memory
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct RenLib {
+    pub version: (u8, u8),
+    /// virtual root, its children are the first moves of the file
+    pub root: LibNode,
+}
+
+impl RenLib {
+    /// parse the bytes of a `.lib` file
+    pub fn parse(bytes: &[u8]) -> Result<Self, FirError> {
+        let invalid = |msg: String| FirError::InvalidRecord(format!("renlib: {msg}"));
+        if bytes.len() < HEADER_LEN || bytes[..MAGIC.len()] != MAGIC {
+            return Err(invalid(String::from("not a RenLib file")));
+        }
+        let version = (bytes[8], bytes[9]);
+
+        // path from the root to the node new moves hang from, and the
+        // parents of nodes that still have a sibling to come
+        let mut lib = RenLib {
+            version,
+            root: LibNode::default(),
+        };
+        let mut path: Vec<usize> = vec![];
+        let mut pending: Vec<Vec<usize>> = vec![];
+        let mut pos = HEADER_LEN;
+
+        while pos + 1 < bytes.len() {
+            let (point, flags) = (bytes[pos], bytes[pos + 1]);
+            pos += 2;
+            if flags & EXTENSION != 0 {
+                return Err(invalid(format!(
+                    "unsupported extension node at {}",
+                    pos - 2
+                )));
+            }
+
+            let mut node = LibNode {
+                coord: if flags & NO_MOVE != 0 {
+                    None
+                } else {
+                    decode_point(point).map_err(|e| invalid(format!("{e} at {}", pos - 2)))?
+                },
+                marked: flags & MARK != 0,
+                start: flags & START != 0,
+                ..LibNode::default()
+            };
+            if flags & (COMMENT | OLD_COMMENT) != 0 {
+                let (text, next) = read_text(bytes, pos)?;
+                pos = next;
+                let mut lines = text.splitn(2, '\n');
+                node.title = lines.next().filter(|t| !t.is_empty()).map(String::from);
+                node.comment = lines.next().filter(|c| !c.is_empty()).map(String::from);
+            }
+
+            let parent = node_at(&mut lib.root, &path);
+            parent.children.push(node);
+            let index = parent.children.len() - 1;
+
+            if flags & RIGHT != 0 {
+                pending.push(path.clone());
+            }
+            if flags & DOWN != 0 {
+                // end of the line, continue with the latest sibling
+                match pending.pop() {
+                    Some(back) => path = back,
+                    None => break,
+                }
+            } else {
+                path.push(index);
+            }
+        }
+        Ok(lib)
+    }
+
+    /// library node reached by `moves` from the empty board
+    pub fn find(&self, moves: &[Coord]) -> Option<&LibNode> {
+        self.root.find(moves)
+    }
+}
+
+fn node_at<'a>(root: &'a mut LibNode, path: &[usize]) -> &'a mut LibNode {
+    let mut node = root;
+    for index in path {
+        node = &mut node.children[*index];
+    }
+    node
+}
+
+fn decode_point(point: u8) -> Result<Option<Coord>, String> {
+    if point == 0 {
+        return Ok(None);
+    }
+    let (column, row) = ((point & 0x0f) as usize, (point >> 4) as usize);
+    if column == 0 || column > RENLIB_SIZE || row >= RENLIB_SIZE {
+        return Err(format!("bad point {point:#04x}"));
+    }
+    // rows count down from the top
+    Ok(Some(Coord {
+        x: (column - 1) as u32,
+        y: (RENLIB_SIZE - 1 - row) as u32,
+    }))
+}
+
+/// zero terminated text read two bytes at a time, and the position after it
+fn read_text(bytes: &[u8], mut pos: usize) -> Result<(String, usize), FirError> {
+    let start = pos;
+    loop {
+        if pos + 1 >= bytes.len() {
+            return Err(FirError::InvalidRecord(format!(
+                "renlib: unterminated comment at {start}"
+            )));
+        }
+        let pair = [bytes[pos], bytes[pos + 1]];
+        pos += 2;
+        if pair.contains(&0) {
+            break;
+        }
+    }
+    let end = bytes[start..pos]
+        .iter()
+        .position(|b| *b == 0)
+        .map_or(pos, |i| start + i);
+    // RenLib writes comments in the system code page, keep what is valid
+    let text = String::from_utf8_lossy(&bytes[start..end]).replace("\r\n", "\n");
+    Ok((text, pos))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// header of version 3.0 followed by `nodes`
+    fn file(nodes: &[u8]) -> Vec<u8> {
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend([3, 0]);
+        bytes.resize(HEADER_LEN, 0);
+        bytes.extend(nodes);
+        bytes
+    }
+
+    /// point byte of a board coordinate
+    fn point(x: u32, y: u32) -> u8 {
+        (((RENLIB_SIZE as u32 - 1 - y) << 4) | (x + 1)) as u8
+    }
+
+    fn coord(x: u32, y: u32) -> Coord {
+        Coord { x, y }
+    }
+
+    #[test]
+    fn minimal_file() {
+        let lib = RenLib::parse(&file(&[point(7, 7), DOWN])).unwrap();
+        assert_eq!(lib.version, (3, 0));
+        assert_eq!(lib.root.count(), 1);
+        assert_eq!(lib.root.children[0].coord, Some(coord(7, 7)));
+        assert!(lib.root.children[0].children.is_empty());
+    }
+
+    #[test]
+    fn empty_tree() {
+        let lib = RenLib::parse(&file(&[])).unwrap();
+        assert_eq!(lib.root.count(), 0);
+    }
+
+    #[test]
+    fn branching_tree() {
+        // h8, then i8 followed by i9, or i7 as a sibling of i8
+        let nodes = [
+            point(7, 7),
+            0,
+            point(8, 7),
+            RIGHT,
+            point(8, 8),
+            DOWN,
+            point(8, 6),
+            DOWN,
+        ];
+        let lib = RenLib::parse(&file(&nodes)).unwrap();
+        assert_eq!(lib.root.count(), 4);
+        let h8 = lib.find(&[coord(7, 7)]).unwrap();
+        let replies: Vec<_> = h8.children.iter().map(|c| c.coord).collect();
+        assert_eq!(replies, vec![Some(coord(8, 7)), Some(coord(8, 6))]);
+        assert!(lib.find(&[coord(7, 7), coord(8, 7), coord(8, 8)]).is_some());
+        assert!(lib.find(&[coord(7, 7), coord(8, 6), coord(8, 8)]).is_none());
+    }
+
+    #[test]
+    fn comments_and_flags() {
+        let mut nodes = vec![point(7, 7), COMMENT | START];
+        nodes.extend(b"Sun\r\nthe main opening\0");
+        nodes.extend([point(8, 8), COMMENT | MARK | DOWN]);
+        // odd text length gets a padding byte
+        nodes.extend(b"Best\0\0");
+        let lib = RenLib::parse(&file(&nodes)).unwrap();
+
+        let first = &lib.root.children[0];
+        assert!(first.start);
+        assert_eq!(first.title.as_deref(), Some("Sun"));
+        assert_eq!(first.comment.as_deref(), Some("the main opening"));
+        let second = &first.children[0];
+        assert!(second.marked);
+        assert_eq!(second.title.as_deref(), Some("Best"));
+        assert_eq!(second.comment, None);
+    }
+
+    #[test]
+    fn node_without_move() {
+        let nodes = [0, NO_MOVE, point(7, 7), DOWN];
+        let lib = RenLib::parse(&file(&nodes)).unwrap();
+        assert_eq!(lib.root.children[0].coord, None);
+        assert_eq!(lib.root.children[0].children[0].coord, Some(coord(7, 7)));
+    }
+
+    #[test]
+    fn invalid_header() {
+        assert!(RenLib::parse(&[]).is_err());
+        assert!(RenLib::parse(&file(&[])[..HEADER_LEN - 1]).is_err());
+        let mut bytes = file(&[point(7, 7), DOWN]);
+        bytes[1] = b'X';
+        assert!(RenLib::parse(&bytes).is_err());
+    }
+
+    #[test]
+    fn invalid_nodes() {
+        // comment that never ends
+        let mut nodes = vec![point(7, 7), COMMENT];
+        nodes.extend(b"no end");
+        assert!(RenLib::parse(&file(&nodes)).is_err());
+        // row without a column
+        assert!(RenLib::parse(&file(&[0x70, DOWN])).is_err());
+        assert!(RenLib::parse(&file(&[point(7, 7), EXTENSION])).is_err());
+    }
+}
@@ -0,0 +1,287 @@
+//! Rule sets and forbidden move detection
+
+use super::{FirError, FirGameState, Order, SqaureState};
+use std::fmt;
+use std::str::FromStr;
+
+/// line directions: horizontal, vertical, diagonal, anti-diagonal
+pub(crate) const DIRECTIONS: [(i32, i32); 4] = [(1, 0), (0, 1), (1, 1), (1, -1)];
+
+/// Which lines win and which moves are prohibited
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub enum RuleSet {
+    /// five or more in a row wins, nothing is forbidden
+    #[default]
+    Freestyle,
+    /// exactly five wins for both sides, an overline does not count
+    Standard,
+    /// black must make exactly five and may not play double-three,
+    /// double-four or overline. white wins with five or more
+    Renju,
+}
+
+impl RuleSet {
+    /// does a run of `len` stones of `order` win the game
+    pub fn is_winning_run(&self, len: usize, order: Order) -> bool {
+        match (self, order) {
+            (RuleSet::Freestyle, _) | (RuleSet::Renju, Order::White) => len >= 5,
+            (RuleSet::Standard, _) | (RuleSet::Renju, Order::Black) => len == 5,
+        }
+    }
+}
+
+impl fmt::Display for RuleSet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            RuleSet::Freestyle => "freestyle",
+            RuleSet::Standard => "standard",
+            RuleSet::Renju => "renju",
+        };
+        f.write_str(name)
+    }
+}
+
+impl FromStr for RuleSet {
+    type Err = FirError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "freestyle" => Ok(RuleSet::Freestyle),
+            "standard" => Ok(RuleSet::Standard),
+            "renju" => Ok(RuleSet::Renju),
+            _ => Err(FirError::InvalidRecord(format!("unknown rule set {s}"))),
+        }
+    }
+}
+
+impl FirGameState {
+    pub(crate) fn in_board(&self, x: i32, y: i32) -> bool {
+        x >= 0 && y >= 0 && (x as usize) < self.size.x && (y as usize) < self.size.y
+    }
+
+    /// square at signed coordinate, `None` when outside of the board
+    pub(crate) fn at(&self, x: i32, y: i32) -> Option<SqaureState> {
+        if self.in_board(x, y) {
+            Some(self.board[self.to1d(x as usize, y as usize)])
+        } else {
+            None
+        }
+    }
+
+    /// length of the run of `stone` through (x, y) along `dir`, counting (x, y) itself
+    pub(crate) fn run_length(&self, x: i32, y: i32, dir: (i32, i32), stone: SqaureState) -> usize {
+        let mut len = 1;
+        for sign in [1, -1] {
+            let mut k = 1;
+            while self.at(x + sign * k * dir.0, y + sign * k * dir.1) == Some(stone) {
+                len += 1;
+                k += 1;
+            }
+        }
+        len
+    }
+
+    /// check whether `order` may play on the empty square (x, y) under `rule`
+    pub(crate) fn is_forbidden(&self, x: usize, y: usize, order: Order, rule: RuleSet) -> bool {
+        if rule != RuleSet::Renju || order == Order::White {
+            return false;
+        }
+
+        let mut state = self.clone();
+        let idx = state.to1d(x, y);
+        state.board[idx] = SqaureState::Black;
+        let (x, y) = (x as i32, y as i32);
+
+        // making exactly five always wins, even through a forbidden shape
+        let runs = DIRECTIONS.map(|dir| state.run_length(x, y, dir, SqaureState::Black));
+        if runs.contains(&5) {
+            return false;
+        }
+        if runs.iter().any(|len| *len > 5) {
+            return true;
+        }
+
+        let fours: usize = DIRECTIONS
+            .iter()
+            .map(|dir| state.count_fours(x, y, *dir))
+            .sum();
+        if fours >= 2 {
+            return true;
+        }
+
+        let threes = DIRECTIONS
+            .iter()
+            .filter(|dir| state.has_open_three(x, y, **dir))
+            .count();
+        threes >= 2
+    }
+
+    /// empty points on the line through (x, y) that complete exactly five black stones
+    fn five_points(&self, x: i32, y: i32, dir: (i32, i32)) -> Vec<i32> {
+        (-4..=4)
+            .filter(|k| {
+                let (px, py) = (x + k * dir.0, y + k * dir.1);
+                if self.at(px, py) != Some(SqaureState::Empty) {
+                    return false;
+                }
+                let mut next = self.clone();
+                let idx = next.to1d(px as usize, py as usize);
+                next.board[idx] = SqaureState::Black;
+                next.run_length(px, py, dir, SqaureState::Black) == 5
+                    && next.run_length(x, y, dir, SqaureState::Black) == 5
+            })
+            .collect()
+    }
+
+    /// number of black fours through (x, y) in one direction
+    ///
+    /// a straight four has two completion points exactly five apart and
+    /// counts once. any other pair of points (`X.XXX.X`) is two fours.
+    fn count_fours(&self, x: i32, y: i32, dir: (i32, i32)) -> usize {
+        let points = self.five_points(x, y, dir);
+        match points.as_slice() {
+            [] => 0,
+            [_] => 1,
+            [a, b] if b - a == 5 => 1,
+            _ => 2,
+        }
+    }
+
+    /// is there a black three through (x, y) that can become a straight four
+    fn has_open_three(&self, x: i32, y: i32, dir: (i32, i32)) -> bool {
+        (-4..=4).any(|k| {
+            let (px, py) = (x + k * dir.0, y + k * dir.1);
+            if k == 0 || self.at(px, py) != Some(SqaureState::Empty) {
+                return false;
+            }
+            let mut next = self.clone();
+            let idx = next.to1d(px as usize, py as usize);
+            next.board[idx] = SqaureState::Black;
+            if next.run_length(px, py, dir, SqaureState::Black) != 4 {
+                return false;
+            }
+            matches!(next.five_points(x, y, dir).as_slice(), [a, b] if b - a == 5)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Coord, FirBoardSize, FirGame};
+
+    /// 15 x 15 board with the given stones
+    fn board(black: &[(usize, usize)], white: &[(usize, usize)]) -> FirGameState {
+        let mut state = FirGameState::empty_board(FirBoardSize::sqaure(15));
+        for &(x, y) in black {
+            state.set_square(x, y, Order::Black).unwrap();
+        }
+        for &(x, y) in white {
+            state.set_square(x, y, Order::White).unwrap();
+        }
+        state
+    }
+
+    fn renju_forbidden(state: &FirGameState, x: usize, y: usize) -> bool {
+        state.is_forbidden(x, y, Order::Black, RuleSet::Renju)
+    }
+
+    #[test]
+    fn winning_runs() {
+        assert!(RuleSet::Freestyle.is_winning_run(6, Order::Black));
+        assert!(!RuleSet::Standard.is_winning_run(6, Order::White));
+        assert!(RuleSet::Standard.is_winning_run(5, Order::White));
+        assert!(!RuleSet::Renju.is_winning_run(6, Order::Black));
+        assert!(RuleSet::Renju.is_winning_run(6, Order::White));
+        assert!(!RuleSet::Freestyle.is_winning_run(4, Order::Black));
+    }
+
+    #[test]
+    fn parse_rule_set() {
+        for rule in [RuleSet::Freestyle, RuleSet::Standard, RuleSet::Renju] {
+            assert_eq!(rule.to_string().parse::<RuleSet>().unwrap(), rule);
+        }
+        assert_eq!("Renju".parse::<RuleSet>().unwrap(), RuleSet::Renju);
+        assert!("gomoku".parse::<RuleSet>().is_err());
+    }
+
+    #[test]
+    fn overline_is_forbidden() {
+        let state = board(&[(2, 7), (3, 7), (4, 7), (6, 7), (7, 7)], &[]);
+        assert!(renju_forbidden(&state, 5, 7));
+        assert!(!state.is_forbidden(5, 7, Order::Black, RuleSet::Freestyle));
+        assert!(!state.is_forbidden(5, 7, Order::White, RuleSet::Renju));
+    }
+
+    #[test]
+    fn double_four_is_forbidden() {
+        let state = board(&[(4, 7), (5, 7), (6, 7), (7, 4), (7, 5), (7, 6)], &[]);
+        assert!(renju_forbidden(&state, 7, 7));
+    }
+
+    #[test]
+    fn double_four_on_one_line_is_forbidden() {
+        // X.XXX.X
+        let state = board(&[(3, 7), (5, 7), (7, 7), (9, 7)], &[]);
+        assert!(renju_forbidden(&state, 6, 7));
+    }
+
+    #[test]
+    fn double_three_is_forbidden() {
+        let state = board(&[(5, 7), (6, 7), (7, 5), (7, 6)], &[]);
+        assert!(renju_forbidden(&state, 7, 7));
+    }
+
+    #[test]
+    fn blocked_three_does_not_count() {
+        let state = board(&[(5, 7), (6, 7), (7, 5), (7, 6)], &[(4, 7)]);
+        assert!(!renju_forbidden(&state, 7, 7));
+    }
+
+    #[test]
+    fn four_three_is_allowed() {
+        let state = board(&[(4, 7), (5, 7), (6, 7), (7, 5), (7, 6)], &[]);
+        assert!(!renju_forbidden(&state, 7, 7));
+    }
+
+    #[test]
+    fn five_wins_through_forbidden_shape() {
+        // the move makes five and a double three at the same time
+        let state = board(
+            &[
+                (3, 7),
+                (4, 7),
+                (5, 7),
+                (6, 7),
+                (7, 5),
+                (7, 6),
+                (8, 8),
+                (9, 9),
+            ],
+            &[],
+        );
+        assert!(!renju_forbidden(&state, 7, 7));
+    }
+
+    #[test]
+    fn play_rejects_forbidden_move() {
+        let moves = [
+            (5, 7),
+            (0, 0),
+            (6, 7),
+            (0, 2),
+            (7, 5),
+            (0, 4),
+            (7, 6),
+            (0, 6),
+        ]
+        .map(|(x, y)| Coord { x, y });
+        let mut game = FirGame::from_moves(15, RuleSet::Renju, &moves).unwrap();
+        assert!(matches!(
+            game.play(7, 7, Order::Black),
+            Err(FirError::ForbiddenMove)
+        ));
+        assert!(!game.legal_moves().contains(&Coord { x: 7, y: 7 }));
+        assert!(game.play(8, 8, Order::Black).is_ok());
+    }
+}
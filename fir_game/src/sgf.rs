@@ -0,0 +1,497 @@
+//! Smart Game Format (`GM[4]`, gomoku and renju)
+//!
+//! Points are written as two letters, column then row, with `aa` in the top
+//! left corner. Rows count down from the top, so row `a` is the last row of
+//! a [FirGame](super::FirGame) board.
+
+use super::{Coord, FirError, GameRecord, Order, RuleSet};
+
+/// one node of the game tree
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SgfNode {
+    /// the `B[..]` or `W[..]` move of the node
+    pub play: Option<(Order, Coord)>,
+    pub comment: Option<String>,
+    /// every other property, as written in the file
+    pub properties: Vec<(String, Vec<String>)>,
+    /// main line first, then variations
+    pub children: Vec<SgfNode>,
+}
+
+impl SgfNode {
+    fn play(order: Order, coord: Coord) -> Self {
+        SgfNode {
+            play: Some((order, coord)),
+            ..SgfNode::default()
+        }
+    }
+
+    /// first value of a property kept in `properties`
+    pub fn property(&self, id: &str) -> Option<&str> {
+        self.properties
+            .iter()
+            .find(|(key, _)| key == id)
+            .and_then(|(_, values)| values.first())
+            .map(String::as_str)
+    }
+}
+
+/// a game with its header and move tree
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SgfGame {
+    /// `SZ`
+    pub size: usize,
+    /// `RU`
+    pub rule: RuleSet,
+    /// `PB` and `PW`
+    pub black: Option<String>,
+    pub white: Option<String>,
+    /// `BR` and `WR`
+    pub black_rating: Option<String>,
+    pub white_rating: Option<String>,
+    /// `RE`, such as `B+`, `W+R`, `B+T` or `0` for a draw
+    pub result: Option<String>,
+    /// `TM`, main time in seconds
+    pub time_limit: Option<u32>,
+    /// `OT`, overtime or increment description
+    pub overtime: Option<String>,
+    /// root node, its children hold the first moves
+    pub root: SgfNode,
+}
+
+impl SgfGame {
+    /// game tree holding the moves of `record` as its main line
+    pub fn from_record(record: &GameRecord) -> Self {
+        let mut game = SgfGame {
+            size: record.size,
+            rule: record.rule,
+            black: record.black.clone(),
+            white: record.white.clone(),
+            ..SgfGame::default()
+        };
+        game.add_variation(0, &record.moves, None);
+        game
+    }
+
+    /// main line as a plain move list
+    pub fn to_record(&self) -> GameRecord {
+        GameRecord {
+            black: self.black.clone(),
+            white: self.white.clone(),
+            ..GameRecord::new(self.size, self.rule, self.main_line())
+        }
+    }
+
+    /// moves of the first child at every node
+    pub fn main_line(&self) -> Vec<Coord> {
+        let mut moves = vec![];
+        let mut node = &self.root;
+        while let Some(next) = node.children.first() {
+            if let Some((_, coord)) = next.play {
+                moves.push(coord);
+            }
+            node = next;
+        }
+        moves
+    }
+
+    /// add `line` as a variation after `ply` moves of the main line
+    ///
+    /// moves the tree already has are followed instead of duplicated, so a
+    /// line that matches the main line extends it. `comment` goes on the
+    /// first new node, for engine analysis and other notes.
+    pub fn add_variation(&mut self, ply: usize, line: &[Coord], comment: Option<String>) {
+        let mut node = &mut self.root;
+        for _ in 0..ply {
+            if node.children.is_empty() {
+                return;
+            }
+            node = &mut node.children[0];
+        }
+
+        let mut order = if ply.is_multiple_of(2) {
+            Order::Black
+        } else {
+            Order::White
+        };
+        let mut comment = comment;
+        for coord in line {
+            let existing = node
+                .children
+                .iter()
+                .position(|child| child.play == Some((order, *coord)));
+            let index = match existing {
+                Some(index) => index,
+                None => {
+                    let mut child = SgfNode::play(order, *coord);
+                    child.comment = comment.take();
+                    node.children.push(child);
+                    node.children.len() - 1
+                }
+            };
+            node = &mut node.children[index];
+            order = order.opponent();
+        }
+    }
+
+    /// read the first game tree of an sgf collection
+    pub fn parse(text: &str) -> Result<Self, FirError> {
+        let mut parser = Parser {
+            chars: text.chars().collect(),
+            pos: 0,
+        };
+        parser.skip_to('(')?;
+        let mut root = parser.game_tree()?;
+
+        let invalid = |msg: String| FirError::InvalidRecord(format!("sgf: {msg}"));
+        if let Some(game) = root.property("GM") {
+            if game != "4" {
+                return Err(invalid(format!("GM[{game}] is not gomoku")));
+            }
+        }
+        let size = match root.property("SZ") {
+            Some(size) => size
+                .split(':')
+                .next()
+                .and_then(|s| s.parse::<usize>().ok())
+                .ok_or_else(|| invalid(format!("bad board size {size}")))?,
+            None => 15,
+        };
+        let rule = match root.property("RU") {
+            Some(rule) => rule.parse().unwrap_or_default(),
+            None => RuleSet::default(),
+        };
+        let text = |id: &str| root.property(id).map(String::from);
+        let mut game = SgfGame {
+            size,
+            rule,
+            black: text("PB"),
+            white: text("PW"),
+            black_rating: text("BR"),
+            white_rating: text("WR"),
+            result: text("RE"),
+            time_limit: root.property("TM").and_then(|t| t.parse().ok()),
+            overtime: text("OT"),
+            root: SgfNode::default(),
+        };
+
+        // header properties live in the struct fields
+        root.properties
+            .retain(|(key, _)| !HEADER.contains(&key.as_str()));
+        resolve_moves(&mut root, size)?;
+        game.root = root;
+        Ok(game)
+    }
+
+    /// write the game as an sgf collection with one game tree
+    pub fn to_sgf(&self) -> String {
+        let mut sgf = String::from("(;GM[4]FF[4]CA[UTF-8]");
+        sgf.push_str(&format!("SZ[{}]", self.size));
+        sgf.push_str(&format!("RU[{}]", self.rule));
+        let header = [
+            ("PB", &self.black),
+            ("PW", &self.white),
+            ("BR", &self.black_rating),
+            ("WR", &self.white_rating),
+            ("RE", &self.result),
+            ("OT", &self.overtime),
+        ];
+        for (id, value) in header {
+            if let Some(value) = value {
+                sgf.push_str(&format!("{id}[{}]", escape(value)));
+            }
+        }
+        if let Some(time) = self.time_limit {
+            sgf.push_str(&format!("TM[{time}]"));
+        }
+        write_properties(&mut sgf, &self.root, self.size);
+        write_children(&mut sgf, &self.root, self.size);
+        sgf.push_str(")\n");
+        sgf
+    }
+}
+
+/// root properties that are read into [SgfGame] fields
+const HEADER: [&str; 13] = [
+    "GM", "FF", "CA", "SZ", "RU", "PB", "PW", "BR", "WR", "RE", "TM", "OT", "AP",
+];
+
+fn escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace(']', "\\]")
+}
+
+fn point_name(coord: Coord, size: usize) -> String {
+    let row = size as u32 - 1 - coord.y;
+    format!(
+        "{}{}",
+        (b'a' + coord.x as u8) as char,
+        (b'a' + row as u8) as char
+    )
+}
+
+fn write_properties(sgf: &mut String, node: &SgfNode, size: usize) {
+    if let Some((order, coord)) = node.play {
+        let id = match order {
+            Order::Black => "B",
+            Order::White => "W",
+        };
+        sgf.push_str(&format!("{id}[{}]", point_name(coord, size)));
+    }
+    if let Some(comment) = &node.comment {
+        sgf.push_str(&format!("C[{}]", escape(comment)));
+    }
+    for (id, values) in node.properties.iter() {
+        sgf.push_str(id);
+        for value in values {
+            sgf.push_str(&format!("[{}]", escape(value)));
+        }
+    }
+}
+
+fn write_children(sgf: &mut String, node: &SgfNode, size: usize) {
+    match node.children.as_slice() {
+        [] => (),
+        [only] => {
+            sgf.push(';');
+            write_properties(sgf, only, size);
+            write_children(sgf, only, size);
+        }
+        children => {
+            for child in children {
+                sgf.push_str("(;");
+                write_properties(sgf, child, size);
+                write_children(sgf, child, size);
+                sgf.push(')');
+            }
+        }
+    }
+}
+
+/// turn `B` and `W` properties into moves, now that the board size is known
+fn resolve_moves(node: &mut SgfNode, size: usize) -> Result<(), FirError> {
+    let mut kept = vec![];
+    for (id, values) in node.properties.drain(..) {
+        let order = match id.as_str() {
+            "B" => Order::Black,
+            "W" => Order::White,
+            "C" => {
+                node.comment = values.first().cloned();
+                continue;
+            }
+            _ => {
+                kept.push((id, values));
+                continue;
+            }
+        };
+        let point = values.first().map(String::as_bytes).unwrap_or_default();
+        let coord = match point {
+            [x, y] if x.is_ascii_lowercase() && y.is_ascii_lowercase() => {
+                let (x, row) = ((x - b'a') as usize, (y - b'a') as usize);
+                if x >= size || row >= size {
+                    return Err(FirError::InvalidRecord(format!(
+                        "sgf: move {id}[{}] is outside of the board",
+                        String::from_utf8_lossy(point)
+                    )));
+                }
+                Coord {
+                    x: x as u32,
+                    y: (size - 1 - row) as u32,
+                }
+            }
+            _ => {
+                return Err(FirError::InvalidRecord(format!(
+                    "sgf: bad move {id}[{}]",
+                    String::from_utf8_lossy(point)
+                )))
+            }
+        };
+        node.play = Some((order, coord));
+    }
+    node.properties = kept;
+    for child in node.children.iter_mut() {
+        resolve_moves(child, size)?;
+    }
+    Ok(())
+}
+
+struct Parser {
+    chars: Vec<char>,
+    pos: usize,
+}
+
+impl Parser {
+    fn error(&self, msg: &str) -> FirError {
+        FirError::InvalidRecord(format!("sgf: {msg} at {}", self.pos))
+    }
+
+    fn peek(&mut self) -> Option<char> {
+        while self.pos < self.chars.len() && self.chars[self.pos].is_whitespace() {
+            self.pos += 1;
+        }
+        self.chars.get(self.pos).copied()
+    }
+
+    fn skip_to(&mut self, c: char) -> Result<(), FirError> {
+        while self.pos < self.chars.len() && self.chars[self.pos] != c {
+            self.pos += 1;
+        }
+        if self.pos < self.chars.len() {
+            Ok(())
+        } else {
+            Err(self.error("no game tree"))
+        }
+    }
+
+    /// `( ;node ;node ... (variation) (variation) )`, returns the first node
+    fn game_tree(&mut self) -> Result<SgfNode, FirError> {
+        if self.peek() != Some('(') {
+            return Err(self.error("expected ("));
+        }
+        self.pos += 1;
+
+        let mut sequence = vec![];
+        while self.peek() == Some(';') {
+            self.pos += 1;
+            sequence.push(self.node()?);
+        }
+        if sequence.is_empty() {
+            return Err(self.error("empty game tree"));
+        }
+        let mut variations = vec![];
+        while self.peek() == Some('(') {
+            variations.push(self.game_tree()?);
+        }
+        if self.peek() != Some(')') {
+            return Err(self.error("expected )"));
+        }
+        self.pos += 1;
+
+        // chain the sequence from the back so each node owns the rest
+        let mut last = sequence.pop().unwrap();
+        last.children = variations;
+        while let Some(mut node) = sequence.pop() {
+            node.children = vec![last];
+            last = node;
+        }
+        Ok(last)
+    }
+
+    fn node(&mut self) -> Result<SgfNode, FirError> {
+        let mut node = SgfNode::default();
+        while let Some(c) = self.peek() {
+            if !c.is_ascii_uppercase() {
+                break;
+            }
+            let mut id = String::new();
+            while let Some(c) = self.chars.get(self.pos).filter(|c| c.is_ascii_alphabetic()) {
+                id.push(*c);
+                self.pos += 1;
+            }
+            let mut values = vec![];
+            while self.peek() == Some('[') {
+                self.pos += 1;
+                values.push(self.value()?);
+            }
+            if values.is_empty() {
+                return Err(self.error("property without value"));
+            }
+            node.properties.push((id, values));
+        }
+        Ok(node)
+    }
+
+    fn value(&mut self) -> Result<String, FirError> {
+        let mut value = String::new();
+        loop {
+            match self.chars.get(self.pos) {
+                None => return Err(self.error("unclosed value")),
+                Some(']') => {
+                    self.pos += 1;
+                    return Ok(value);
+                }
+                Some('\\') => {
+                    // escaped character, a soft line break is dropped
+                    if let Some(c) = self.chars.get(self.pos + 1) {
+                        if *c != '\n' {
+                            value.push(*c);
+                        }
+                    }
+                    self.pos += 2;
+                }
+                Some(c) => {
+                    value.push(*c);
+                    self.pos += 1;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn coords(moves: &[(u32, u32)]) -> Vec<Coord> {
+        moves.iter().map(|&(x, y)| Coord { x, y }).collect()
+    }
+
+    #[test]
+    fn parse_header_and_main_line() {
+        let text = "(;GM[4]FF[4]SZ[15]RU[renju]PB[alice]PW[bob]RE[B+]TM[300]OT[fischer 3]
+            ;B[hh];W[ih]C[a comment with \\] inside];B[hi])";
+        let game = SgfGame::parse(text).unwrap();
+        assert_eq!(game.size, 15);
+        assert_eq!(game.rule, RuleSet::Renju);
+        assert_eq!(game.black.as_deref(), Some("alice"));
+        assert_eq!(game.result.as_deref(), Some("B+"));
+        assert_eq!(game.time_limit, Some(300));
+        assert_eq!(game.overtime.as_deref(), Some("fischer 3"));
+        // row `a` is the top row, the last row of the board
+        assert_eq!(game.main_line(), coords(&[(7, 7), (8, 7), (7, 6)]));
+        let second = &game.root.children[0].children[0];
+        assert_eq!(second.comment.as_deref(), Some("a comment with ] inside"));
+        assert!(game.root.properties.is_empty());
+    }
+
+    #[test]
+    fn round_trip_with_variations() {
+        let mut record = GameRecord::new(15, RuleSet::Standard, coords(&[(7, 7), (8, 8), (9, 9)]));
+        record.black = Some(String::from("alice"));
+        let mut game = SgfGame::from_record(&record);
+        game.result = Some(String::from("W+R"));
+        game.add_variation(1, &coords(&[(6, 6), (5, 5)]), Some(String::from("better")));
+        game.add_variation(2, &coords(&[(9, 9), (10, 10)]), None);
+        game.root
+            .properties
+            .push((String::from("DT"), vec![String::from("2024-01-01")]));
+
+        let text = game.to_sgf();
+        let parsed = SgfGame::parse(&text).unwrap();
+        assert_eq!(parsed, game);
+        assert_eq!(parsed.to_sgf(), text);
+        assert_eq!(
+            parsed.main_line(),
+            coords(&[(7, 7), (8, 8), (9, 9), (10, 10)])
+        );
+        assert_eq!(parsed.root.children[0].children.len(), 2);
+        assert_eq!(parsed.to_record().moves, parsed.main_line());
+    }
+
+    #[test]
+    fn variation_matching_main_line_is_not_duplicated() {
+        let record = GameRecord::new(15, RuleSet::Freestyle, coords(&[(7, 7), (8, 8)]));
+        let mut game = SgfGame::from_record(&record);
+        game.add_variation(0, &coords(&[(7, 7), (8, 8)]), None);
+        assert_eq!(game.root.children.len(), 1);
+        assert_eq!(game.root.children[0].children.len(), 1);
+    }
+
+    #[test]
+    fn reject_broken_sgf() {
+        assert!(SgfGame::parse("no tree here").is_err());
+        assert!(SgfGame::parse("(;GM[1]SZ[19];B[aa])").is_err());
+        assert!(SgfGame::parse("(;GM[4]SZ[15];B[zz])").is_err());
+        assert!(SgfGame::parse("(;GM[4]SZ[15];B[hh]").is_err());
+        assert!(SgfGame::parse("(;GM[4]C[unclosed)").is_err());
+    }
+}
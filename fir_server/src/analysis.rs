@@ -0,0 +1,59 @@
+use crate::database::data::Notation;
+use crate::database::info;
+use fir_game::{Coord, FirError, FirGame, Order};
+
+/// deepest search a client may ask for
+const MAX_DEPTH: u32 = 4;
+/// most suggestions a client may ask for
+const MAX_COUNT: u32 = 10;
+
+fn to_notation(coord: Coord, order: Order) -> info::NotationInfo {
+    Notation {
+        color: order == Order::Black,
+        x: coord.x,
+        y: coord.y,
+    }
+    .into()
+}
+
+/// replay the given moves and evaluate the position for the side to move
+pub fn analyze(request: info::AnalyzeInfo) -> Result<info::AnalysisInfo, FirError> {
+    let mut game = FirGame::new();
+    for notation in request.notations {
+        let notation: Notation = notation.into();
+        game.play(notation.x, notation.y, game.order())?;
+    }
+
+    let depth = request.depth.clamp(1, MAX_DEPTH);
+    let count = request.count.clamp(1, MAX_COUNT) as usize;
+    let evaluation = game.evaluate(depth, count);
+
+    let suggestions = evaluation
+        .suggestions
+        .into_iter()
+        .map(|suggestion| {
+            // moves in the line alternate, starting with the side to move
+            let mut order = game.order();
+            let pv = suggestion
+                .pv
+                .into_iter()
+                .map(|coord| {
+                    let notation = to_notation(coord, order);
+                    order = order.opponent();
+                    notation
+                })
+                .collect();
+            info::SuggestionInfo {
+                x: suggestion.coord.x,
+                y: suggestion.coord.y,
+                score: suggestion.score,
+                pv,
+            }
+        })
+        .collect();
+
+    Ok(info::AnalysisInfo {
+        score: evaluation.score,
+        suggestions,
+    })
+}
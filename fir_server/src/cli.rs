@@ -1,22 +1,24 @@
-use tokio::sync::Mutex;
+use tokio::sync::RwLock;
 
 use crate::database::data;
 use crate::database::data::Notation;
 use crate::database::{data::*, Database, DbSender, UpdateQuery};
 use crate::prelude::*;
+use fir_game::{GameRecord, GifOptions, PuzzleGenerator, SgfGame};
 use std::io;
+use std::io::Write;
 use std::sync::Arc;
 
 pub async fn run(
     tx: DbSender,
-    db: Arc<Mutex<Database>>,
+    db: Arc<RwLock<Database>>,
 ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     loop {
         let mut buf = String::new();
         io::stdin().read_line(&mut buf).unwrap();
         let command = buf
             .split_whitespace()
-            .map(|str| String::from(str))
+            .map(String::from)
             .collect::<Vec<String>>();
 
         log(&format!("command {}", command[0]));
@@ -30,13 +32,119 @@ pub async fn run(
             })
             .await?;
         } else if command[0] == "printdb" {
-            let data = db.lock().await;
+            let data = db.read().await;
             let user_data = data.get_all_user();
             println!("USER INFO");
             println!("{user_data:?}");
             let game_data = data.get_all_game();
             println!("GAME INFO");
             println!("{game_data:?}");
+        } else if command[0] == "board" {
+            // board <game id>
+            let id = command.get(1).and_then(|i| i.parse::<u64>().ok());
+            let data = db.read().await;
+            match id.and_then(|id| data.get_game(id)) {
+                Some(game) => match game.replay() {
+                    Ok(game) => println!("{}", game.to_unicode()),
+                    Err(e) => log(&format!("broken game record: {e}")),
+                },
+                None => log("usage: board <game id>"),
+            }
+        } else if command[0] == "gif" {
+            // gif <game id> <output file> [delay ms]
+            let id = command.get(1).and_then(|i| i.parse::<u64>().ok());
+            let mut options = GifOptions::default();
+            if let Some(delay) = command.get(3).and_then(|d| d.parse().ok()) {
+                options.delay_ms = delay;
+            }
+            let data = db.read().await;
+            match (id.and_then(|id| data.get_game(id)), command.get(2)) {
+                (Some(game), Some(path)) => {
+                    let written = game
+                        .to_gif(&options)
+                        .map_err(|e| e.to_string())
+                        .and_then(|gif| std::fs::write(path, gif).map_err(|e| e.to_string()));
+                    match written {
+                        Ok(()) => log(&format!("write game {} to {path}", command[1])),
+                        Err(e) => log(&format!("fail to write {path}: {e}")),
+                    }
+                }
+                _ => log("usage: gif <game id> <output file> [delay ms]"),
+            }
+        } else if command[0] == "importpsq" {
+            // importpsq <psq file>...
+            for path in command.iter().skip(1) {
+                let game = std::fs::read_to_string(path)
+                    .map_err(|e| e.to_string())
+                    .and_then(|text| GameRecord::from_psq(&text).map_err(|e| e.to_string()))
+                    .and_then(|record| GameData::from_record(record).map_err(|e| e.to_string()));
+                match game {
+                    Ok(game) => tx.send(UpdateQuery::GameData(game)).await?,
+                    Err(e) => log(&format!("fail to import {path}: {e}")),
+                }
+            }
+        } else if command[0] == "exportpsq" {
+            // exportpsq <game id> <output file>
+            let id = command.get(1).and_then(|i| i.parse::<u64>().ok());
+            let data = db.read().await;
+            match (id.and_then(|id| data.get_game(id)), command.get(2)) {
+                (Some(game), Some(path)) => match std::fs::write(path, game.to_record().to_psq()) {
+                    Ok(()) => log(&format!("write game {} to {path}", command[1])),
+                    Err(e) => log(&format!("fail to write {path}: {e}")),
+                },
+                _ => log("usage: exportpsq <game id> <output file>"),
+            }
+        } else if command[0] == "importsgf" {
+            // importsgf <sgf file>...
+            for path in command.iter().skip(1) {
+                let game = std::fs::read_to_string(path)
+                    .map_err(|e| e.to_string())
+                    .and_then(|text| SgfGame::parse(&text).map_err(|e| e.to_string()))
+                    .and_then(|sgf| GameData::from_sgf(sgf).map_err(|e| e.to_string()));
+                match game {
+                    Ok(game) => tx.send(UpdateQuery::GameData(game)).await?,
+                    Err(e) => log(&format!("fail to import {path}: {e}")),
+                }
+            }
+        } else if command[0] == "exportsgf" {
+            // exportsgf <game id> <output file>
+            let id = command.get(1).and_then(|i| i.parse::<u64>().ok());
+            let data = db.read().await;
+            match (id.and_then(|id| data.get_game(id)), command.get(2)) {
+                (Some(game), Some(path)) => match std::fs::write(path, game.to_sgf().to_sgf()) {
+                    Ok(()) => log(&format!("write game {} to {path}", command[1])),
+                    Err(e) => log(&format!("fail to write {path}: {e}")),
+                },
+                _ => log("usage: exportsgf <game id> <output file>"),
+            }
+        } else if command[0] == "puzzle" {
+            // puzzle <vcf|vct> <max length> <output file>
+            let usage = format!(
+                "usage: puzzle <vcf|vct> <max length 1-{}> <output file>",
+                PuzzleGenerator::MAX_LENGTH
+            );
+            if command.len() < 4 {
+                log(&usage);
+                continue;
+            }
+            let length = command[2]
+                .parse()
+                .ok()
+                .filter(|l| (1..=PuzzleGenerator::MAX_LENGTH).contains(l));
+            let (Ok(kind), Some(length)) = (command[1].parse(), length) else {
+                log(&usage);
+                continue;
+            };
+            let games = db.read().await.get_all_game().to_vec();
+            let path = command[3].clone();
+            let count = tokio::task::spawn_blocking(move || {
+                write_puzzles(&games, PuzzleGenerator::new(kind, length), &path)
+            })
+            .await?;
+            match count {
+                Ok(count) => log(&format!("write {count} puzzles to {}", command[3])),
+                Err(e) => log(&format!("fail to write puzzles to {}: {e}", command[3])),
+            }
         } else {
             log(&format!("unkown command {}", command[0]));
         }
@@ -44,20 +152,28 @@ pub async fn run(
     Ok(())
 }
 
+/// scan stored games for puzzles and write them one per line
+fn write_puzzles(
+    games: &[GameData],
+    generator: PuzzleGenerator,
+    path: &str,
+) -> Result<usize, Box<dyn std::error::Error + Send + Sync>> {
+    let mut file = std::fs::File::create(path)?;
+    let mut count = 0;
+    for game in games {
+        let record = game.to_record();
+        for puzzle in generator.scan_game(record.size, record.rule, &record.moves) {
+            writeln!(file, "{puzzle}")?;
+            count += 1;
+        }
+    }
+    Ok(count)
+}
+
 async fn add_sample_datas(tx: DbSender) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     let user_info = vec![
-        data::UserData {
-            id: String::from("Alice"),
-            pwd: String::from("1234"),
-            rating: 100,
-            key: String::from("Alice_key"),
-        },
-        data::UserData {
-            id: String::from("Jonathan"),
-            pwd: String::from("qwerty"),
-            rating: 200,
-            key: String::from("Jonathan_key"),
-        },
+        data::UserData::new(String::from("Alice"), String::from("1234"), 100),
+        data::UserData::new(String::from("Jonathan"), String::from("qwerty"), 200),
     ];
     let game_info = vec![
         data::GameData {
@@ -96,6 +212,16 @@ async fn add_sample_datas(tx: DbSender) -> Result<(), Box<dyn std::error::Error
                     y: 7,
                 },
             ],
+            board_size: 8,
+            id: 0,
+            termination: Some(Termination::DrawAgreement),
+            time: None,
+            rule: fir_game::RuleSet::Freestyle,
+            started_at: None,
+            ended_at: None,
+            move_times: vec![],
+            rated: false,
+            rating_change: None,
         },
         data::GameData {
             black_user: user_info[1].clone(),
@@ -138,6 +264,16 @@ async fn add_sample_datas(tx: DbSender) -> Result<(), Box<dyn std::error::Error
                     y: 1,
                 },
             ],
+            board_size: 8,
+            id: 0,
+            termination: Some(Termination::Five),
+            time: None,
+            rule: fir_game::RuleSet::Freestyle,
+            started_at: None,
+            ended_at: None,
+            move_times: vec![],
+            rated: false,
+            rating_change: None,
         },
     ];
 
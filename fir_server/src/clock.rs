@@ -0,0 +1,159 @@
+//! Game clocks
+//!
+//! The server keeps the time of both players. The side to move loses time
+//! until it moves, then gets the Fischer increment. Time is read from
+//! [tokio::time::Instant], so paused tokio time drives the clock as well.
+
+use crate::database::data::{Side, TimeControl};
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// remaining time of both players
+#[derive(Debug, Clone, Copy)]
+pub struct Clocks {
+    pub black: Duration,
+    pub white: Duration,
+}
+
+#[derive(Debug, Clone)]
+pub struct Clock {
+    /// black, white
+    remaining: [Duration; 2],
+    increment: Duration,
+    turn: Side,
+    /// when the side to move started thinking
+    since: Instant,
+}
+
+impl Clock {
+    /// clock for a new game, black's time running from `now`
+    pub fn new(time: TimeControl, now: Instant) -> Self {
+        let start = Duration::from_secs(time.seconds as u64);
+        Clock {
+            remaining: [start, start],
+            increment: Duration::from_secs(time.fisher as u64),
+            turn: Side::Black,
+            since: now,
+        }
+    }
+
+    /// time `side` has left at `now`
+    pub fn remaining(&self, side: Side, now: Instant) -> Duration {
+        let remaining = self.remaining[side.index()];
+        if side == self.turn {
+            remaining.saturating_sub(now.saturating_duration_since(self.since))
+        } else {
+            remaining
+        }
+    }
+
+    pub fn clocks(&self, now: Instant) -> Clocks {
+        Clocks {
+            black: self.remaining(Side::Black, now),
+            white: self.remaining(Side::White, now),
+        }
+    }
+
+    /// when the side to move runs out of time
+    pub fn deadline(&self) -> Instant {
+        self.since + self.remaining[self.turn.index()]
+    }
+
+    /// end the move of the side to move at `now`
+    ///
+    /// returns the time it has left with the increment, or `None` if its
+    /// time ran out first.
+    pub fn press(&mut self, now: Instant) -> Option<Duration> {
+        let left = self.remaining(self.turn, now);
+        if left.is_zero() {
+            return None;
+        }
+        let left = left + self.increment;
+        self.remaining[self.turn.index()] = left;
+        self.turn = self.turn.opponent();
+        self.since = now;
+        Some(left)
+    }
+
+    /// give the move back to `side` without an increment
+    ///
+    /// the time used so far stays charged to the side that was moving.
+    pub fn set_turn(&mut self, side: Side, now: Instant) {
+        if side == self.turn {
+            return;
+        }
+        self.remaining[self.turn.index()] = self.remaining(self.turn, now);
+        self.turn = side;
+        self.since = now;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::time::advance;
+
+    fn clock(seconds: u32, fisher: u32) -> Clock {
+        Clock::new(TimeControl { seconds, fisher }, Instant::now())
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn time_runs_for_the_side_to_move() {
+        let clock = clock(60, 0);
+        let start = Instant::now();
+        assert_eq!(clock.deadline(), start + Duration::from_secs(60));
+
+        advance(Duration::from_secs(15)).await;
+        let now = Instant::now();
+        assert_eq!(clock.remaining(Side::Black, now), Duration::from_secs(45));
+        assert_eq!(clock.remaining(Side::White, now), Duration::from_secs(60));
+        // the deadline does not move while the time runs
+        assert_eq!(clock.deadline(), start + Duration::from_secs(60));
+
+        advance(Duration::from_secs(60)).await;
+        assert_eq!(clock.clocks(Instant::now()).black, Duration::ZERO);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn press_adds_the_increment() {
+        let mut clock = clock(60, 5);
+        advance(Duration::from_secs(10)).await;
+        assert_eq!(clock.press(Instant::now()), Some(Duration::from_secs(55)));
+
+        advance(Duration::from_secs(20)).await;
+        let now = Instant::now();
+        let clocks = clock.clocks(now);
+        assert_eq!(clocks.black, Duration::from_secs(55));
+        assert_eq!(clocks.white, Duration::from_secs(40));
+        assert_eq!(clock.deadline(), now + Duration::from_secs(40));
+        assert_eq!(clock.press(now), Some(Duration::from_secs(45)));
+        assert_eq!(clock.deadline(), now + Duration::from_secs(55));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn press_after_the_flag_fell() {
+        let mut clock = clock(10, 5);
+        advance(Duration::from_secs(10)).await;
+        assert_eq!(clock.press(Instant::now()), None);
+        // still black to move, without the increment
+        assert_eq!(
+            clock.remaining(Side::White, Instant::now()),
+            Duration::from_secs(10)
+        );
+        assert_eq!(clock.remaining(Side::Black, Instant::now()), Duration::ZERO);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn set_turn_keeps_the_time_used() {
+        let mut clock = clock(60, 5);
+        advance(Duration::from_secs(10)).await;
+        clock.press(Instant::now());
+        advance(Duration::from_secs(20)).await;
+        // white's move is taken back, black moves again without increment
+        clock.set_turn(Side::Black, Instant::now());
+        advance(Duration::from_secs(5)).await;
+        let clocks = clock.clocks(Instant::now());
+        assert_eq!(clocks.white, Duration::from_secs(40));
+        assert_eq!(clocks.black, Duration::from_secs(50));
+    }
+}
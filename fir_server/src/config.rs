@@ -0,0 +1,94 @@
+use crate::prelude::*;
+use crate::rating::RatingSystem;
+use std::env;
+use std::path::PathBuf;
+
+/// login lifetime when `FIR_SESSION_HOURS` is unset or unusable
+const DEFAULT_SESSION_HOURS: i64 = 24 * 7;
+
+/// where the data is kept
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StorageKind {
+    /// lost on exit, for tests and trying things out
+    Memory,
+    /// journal and snapshots in the data directory
+    Journal,
+}
+
+/// server settings
+///
+/// read from environment variables so the same binary runs anywhere.
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// `FIR_STORAGE`, `memory` or `journal`
+    pub storage: StorageKind,
+    /// `FIR_DATA_DIR`, where users and games are stored
+    pub data_dir: PathBuf,
+    /// `FIR_SESSION_HOURS`, how long a login lasts
+    pub session_ttl: chrono::Duration,
+    /// `FIR_RATING`, `elo` or `glicko2`
+    pub rating: RatingSystem,
+    /// `FIR_RATED_TAKEBACKS`, whether rated games allow takebacks
+    pub rated_takebacks: bool,
+    /// `FIR_RECONNECT_SECONDS`, how long a game waits for a player who
+    /// lost the connection
+    pub reconnect_grace: std::time::Duration,
+}
+
+impl Config {
+    pub fn from_env() -> Self {
+        let storage = match env::var("FIR_STORAGE").as_deref() {
+            Ok("memory") => StorageKind::Memory,
+            Ok("journal") | Err(_) => StorageKind::Journal,
+            Ok(other) => {
+                log(&format!("unknown storage {other}, use journal"));
+                StorageKind::Journal
+            }
+        };
+        let rating = match env::var("FIR_RATING").as_deref() {
+            Ok("glicko2") => RatingSystem::Glicko2,
+            Ok("elo") | Err(_) => RatingSystem::Elo,
+            Ok(other) => {
+                log(&format!("unknown rating system {other}, use elo"));
+                RatingSystem::Elo
+            }
+        };
+        let session_ttl = match env::var("FIR_SESSION_HOURS") {
+            Ok(hours) => match hours
+                .parse()
+                .ok()
+                .filter(|h| *h > 0)
+                .and_then(chrono::Duration::try_hours)
+                // sessions end at now + ttl, which has to be a date
+                .filter(|ttl| chrono::Utc::now().checked_add_signed(*ttl).is_some())
+            {
+                Some(ttl) => ttl,
+                None => {
+                    log(&format!(
+                        "invalid session hours {hours}, use {DEFAULT_SESSION_HOURS}"
+                    ));
+                    chrono::Duration::hours(DEFAULT_SESSION_HOURS)
+                }
+            },
+            Err(_) => chrono::Duration::hours(DEFAULT_SESSION_HOURS),
+        };
+        Config {
+            storage,
+            rating,
+            rated_takebacks: matches!(
+                env::var("FIR_RATED_TAKEBACKS").as_deref(),
+                Ok("1") | Ok("true")
+            ),
+            reconnect_grace: std::time::Duration::from_secs(
+                env::var("FIR_RECONNECT_SECONDS")
+                    .ok()
+                    .and_then(|s| s.parse().ok())
+                    .unwrap_or(60),
+            ),
+            data_dir: env::var_os("FIR_DATA_DIR")
+                .map(PathBuf::from)
+                .unwrap_or_else(|| PathBuf::from("data")),
+            session_ttl,
+        }
+    }
+}
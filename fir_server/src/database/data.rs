@@ -1,44 +1,198 @@
 /// 게임 전반으로 사용되는 데이터들
 use super::info::{self, NotationInfo};
-use fir_game::Order;
+use crate::clock::Clocks;
+use crate::rating;
+use chrono::{DateTime, Utc};
+use fir_game::{GameRecord, Order, RuleSet, SgfGame};
 use serde::{Deserialize, Serialize};
+use std::fmt;
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Serialize, Deserialize)]
 pub struct UserData {
     pub id: String,
     pub pwd: String,
     pub rating: u32,
-    pub key: String,
+    /// rated games played
+    #[serde(default)]
+    pub games: u32,
+    /// Glicko-2 rating deviation
+    #[serde(default = "start_deviation")]
+    pub deviation: f64,
+    /// Glicko-2 volatility
+    #[serde(default = "start_volatility")]
+    pub volatility: f64,
+    /// end of the last rated game
+    #[serde(default)]
+    pub rated_at: Option<DateTime<Utc>>,
 }
 
-impl Into<info::UserInfo> for UserData {
-    fn into(self) -> info::UserInfo {
-        info::UserInfo {
-            id: self.id,
-            pwd: self.pwd,
-            rating: self.rating,
-            key: self.key,
-        }
+/// the password hash is left out so dumps and logs never show it
+impl fmt::Debug for UserData {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("UserData")
+            .field("id", &self.id)
+            .field("pwd", &"<redacted>")
+            .field("rating", &self.rating)
+            .field("games", &self.games)
+            .field("deviation", &self.deviation)
+            .field("volatility", &self.volatility)
+            .field("rated_at", &self.rated_at)
+            .finish()
     }
 }
 
-impl Into<UserData> for info::UserInfo {
-    fn into(self) -> UserData {
+fn start_deviation() -> f64 {
+    rating::MAX_DEVIATION
+}
+
+fn start_volatility() -> f64 {
+    rating::START_VOLATILITY
+}
+
+impl UserData {
+    /// user who has not played a rated game yet
+    pub fn new(id: String, pwd: String, rating: u32) -> Self {
         UserData {
-            id: self.id,
-            pwd: self.pwd,
+            id,
+            pwd,
+            rating,
+            games: 0,
+            deviation: start_deviation(),
+            volatility: start_volatility(),
+            rated_at: None,
+        }
+    }
+
+    /// player connected without logging in
+    pub fn anonymous() -> Self {
+        UserData::new(String::from("Anonymous"), String::new(), 0)
+    }
+
+    pub fn is_anonymous(&self) -> bool {
+        self.id == "Anonymous"
+    }
+
+    /// start of the ids of imported players, which registered ids cannot contain
+    pub const IMPORTED_PREFIX: &'static str = "import:";
+
+    /// player of an imported game, kept apart from accounts of the same name
+    pub fn imported(name: &str) -> Self {
+        UserData {
+            id: format!("{}{name}", Self::IMPORTED_PREFIX),
+            ..UserData::anonymous()
+        }
+    }
+
+    /// name written to game records, without the import prefix
+    pub fn record_name(&self) -> &str {
+        self.id
+            .strip_prefix(Self::IMPORTED_PREFIX)
+            .unwrap_or(&self.id)
+    }
+
+    pub fn rating_player(&self) -> rating::Player {
+        rating::Player {
             rating: self.rating,
-            key: self.key,
+            games: self.games,
+        }
+    }
+
+    /// Glicko-2 player at `now`, the deviation grown for the time since
+    /// the last rated game
+    pub fn glicko(&self, now: DateTime<Utc>) -> rating::Glicko2 {
+        let player = rating::Glicko2 {
+            rating: self.rating as f64,
+            deviation: self.deviation,
+            volatility: self.volatility,
+        };
+        match self.rated_at {
+            Some(rated_at) => {
+                let days = (now - rated_at).num_seconds() as f64 / (24.0 * 60.0 * 60.0);
+                player.decay(days / rating::RATING_PERIOD_DAYS)
+            }
+            None => player,
         }
     }
+
+    pub fn set_glicko(&mut self, player: rating::Glicko2, now: DateTime<Utc>) {
+        self.rating = player.rating.round().max(0.0) as u32;
+        self.deviation = player.deviation;
+        self.volatility = player.volatility;
+        self.rated_at = Some(now);
+    }
 }
 
-#[derive(Debug, Clone, Copy)]
+impl From<UserData> for info::UserInfo {
+    fn from(value: UserData) -> Self {
+        info::UserInfo {
+            provisional: value.rating_player().is_provisional(),
+            id: value.id,
+            rating: value.rating,
+            games: value.games,
+            deviation: value.deviation.round() as u32,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct TimeControl {
     pub seconds: u32,
     pub fisher: u32,
 }
 
+impl TimeControl {
+    /// longest game a player can ask for, in seconds
+    pub const MAX_SECONDS: u32 = 3 * 60 * 60;
+    /// largest increment a player can ask for, in seconds
+    pub const MAX_FISHER: u32 = 60;
+
+    /// whether the server plays games with this time control
+    pub fn is_valid(&self) -> bool {
+        (1..=Self::MAX_SECONDS).contains(&self.seconds) && self.fisher <= Self::MAX_FISHER
+    }
+
+    /// time control from the sgf `TM` main time and `OT` overtime
+    ///
+    /// only a fischer increment such as `fischer 2` is read from `OT`, other
+    /// overtime systems give no increment.
+    pub fn from_sgf(time_limit: u32, overtime: Option<&str>) -> Self {
+        let fisher = overtime
+            .filter(|ot| ot.to_ascii_lowercase().contains("fischer"))
+            .and_then(|ot| {
+                ot.split(|c: char| !c.is_ascii_digit())
+                    .find(|n| !n.is_empty())
+            })
+            .and_then(|n| n.parse().ok())
+            .unwrap_or(0);
+        TimeControl {
+            seconds: time_limit,
+            fisher,
+        }
+    }
+}
+
+/// written as `seconds+fisher`, such as `180+2`
+impl fmt::Display for TimeControl {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}+{}", self.seconds, self.fisher)
+    }
+}
+
+/// read from `seconds+fisher` or plain `seconds`
+///
+/// a space works as well as `+`, which is what `+` means in a query string.
+impl std::str::FromStr for TimeControl {
+    type Err = std::num::ParseIntError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (seconds, fisher) = s.split_once(['+', ' ']).unwrap_or((s, "0"));
+        Ok(TimeControl {
+            seconds: seconds.trim().parse()?,
+            fisher: fisher.trim().parse()?,
+        })
+    }
+}
+
 #[derive(Debug, Clone, Copy, Serialize, Deserialize)]
 pub struct Notation {
     pub color: bool,
@@ -46,31 +200,31 @@ pub struct Notation {
     pub y: u32,
 }
 
-impl Into<info::NotationInfo> for Notation {
-    fn into(self) -> info::NotationInfo {
+impl From<Notation> for info::NotationInfo {
+    fn from(value: Notation) -> Self {
         NotationInfo {
-            color: if self.color { 1 } else { 0 },
-            x: self.x,
-            y: self.y,
+            color: if value.color { 1 } else { 0 },
+            x: value.x,
+            y: value.y,
         }
     }
 }
 
-impl Into<Notation> for info::NotationInfo {
-    fn into(self) -> Notation {
+impl From<info::NotationInfo> for Notation {
+    fn from(value: info::NotationInfo) -> Self {
         Notation {
-            color: if self.color == 1 { true } else { false },
-            x: self.x,
-            y: self.y,
+            color: value.color == 1,
+            x: value.x,
+            y: value.y,
         }
     }
 }
 
-impl Into<fir_game::Coord> for Notation {
-    fn into(self) -> fir_game::Coord {
+impl From<Notation> for fir_game::Coord {
+    fn from(value: Notation) -> Self {
         fir_game::Coord {
-            x: self.x,
-            y: self.y,
+            x: value.x,
+            y: value.y,
         }
     }
 }
@@ -82,68 +236,442 @@ pub enum GameResult {
     Draw,
     Abort,
     OnGoing,
+    /// no result recorded, such as an imported game that ends without a five
+    Unknown,
+}
+
+impl GameResult {
+    /// `RE` value of an sgf file
+    pub fn to_sgf(&self) -> Option<String> {
+        let result = match self {
+            GameResult::Win(Side::Black) => "B+",
+            GameResult::Win(Side::White) => "W+",
+            GameResult::Resign(Side::Black) => "W+R",
+            GameResult::Resign(Side::White) => "B+R",
+            GameResult::Draw => "0",
+            GameResult::Abort => "Void",
+            GameResult::Unknown => "?",
+            GameResult::OnGoing => return None,
+        };
+        Some(String::from(result))
+    }
+
+    /// result from an sgf `RE` value, `None` if unknown
+    pub fn from_sgf(result: &str) -> Option<Self> {
+        let result = result.trim();
+        let resign = result.ends_with("+R") || result.ends_with("+Resign");
+        match result {
+            "0" | "Draw" => Some(GameResult::Draw),
+            "Void" => Some(GameResult::Abort),
+            "?" => Some(GameResult::Unknown),
+            r if r.starts_with("B+") && resign => Some(GameResult::Resign(Side::White)),
+            r if r.starts_with("W+") && resign => Some(GameResult::Resign(Side::Black)),
+            r if r.starts_with("B+") => Some(GameResult::Win(Side::Black)),
+            r if r.starts_with("W+") => Some(GameResult::Win(Side::White)),
+            _ => None,
+        }
+    }
+}
+
+/// written as sent to clients, such as `black win`
+impl fmt::Display for GameResult {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            GameResult::Win(Side::White) => "white win",
+            GameResult::Win(Side::Black) => "black win",
+            GameResult::Resign(Side::White) => "white resign",
+            GameResult::Resign(Side::Black) => "black resign",
+            GameResult::Draw => "draw",
+            GameResult::Abort => "abort",
+            GameResult::OnGoing => "ongoing",
+            GameResult::Unknown => "unknown",
+        };
+        f.write_str(name)
+    }
+}
+
+/// how a game ended
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum Termination {
+    /// five in a row
+    Five,
+    Resign,
+    Timeout,
+    Disconnect,
+    DrawAgreement,
+}
+
+impl fmt::Display for Termination {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Termination::Five => "five",
+            Termination::Resign => "resign",
+            Termination::Timeout => "timeout",
+            Termination::Disconnect => "disconnect",
+            Termination::DrawAgreement => "draw agreement",
+        };
+        f.write_str(name)
+    }
+}
+
+/// when a move was played
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct MoveTime {
+    pub played_at: DateTime<Utc>,
+    /// time left on the mover's clock, in milliseconds
+    pub clock_ms: Option<u64>,
+}
+
+/// ratings after a rated game
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct RatingChange {
+    pub black: u32,
+    pub black_change: i32,
+    pub white: u32,
+    pub white_change: i32,
+}
+
+impl From<RatingChange> for info::RatingInfo {
+    fn from(value: RatingChange) -> Self {
+        info::RatingInfo {
+            black: value.black,
+            blackchange: value.black_change,
+            white: value.white,
+            whitechange: value.white_change,
+        }
+    }
+}
+
+impl From<Clocks> for info::ClockInfo {
+    fn from(value: Clocks) -> Self {
+        info::ClockInfo {
+            black: value.black.as_millis() as u64,
+            white: value.white.as_millis() as u64,
+        }
+    }
+}
+
+/// position of a game in progress
+#[derive(Debug, Clone)]
+pub struct GameState {
+    pub black: String,
+    pub white: String,
+    pub rule: RuleSet,
+    pub time: Option<TimeControl>,
+    pub turn: Side,
+    /// rows from the top
+    pub board: Vec<String>,
+    pub moves: Vec<Notation>,
+    pub clocks: Clocks,
+}
+
+impl From<GameState> for info::GameStateInfo {
+    fn from(value: GameState) -> Self {
+        info::GameStateInfo {
+            black: value.black,
+            white: value.white,
+            rule: value.rule.to_string(),
+            timecontrol: value.time.map(|t| t.to_string()).unwrap_or_default(),
+            turn: value.turn.index() as u32,
+            board: value.board,
+            moves: value.moves.into_iter().map(|n| n.into()).collect(),
+            clocks: value.clocks.into(),
+        }
+    }
+}
+
+/// where a player stands in the match queue
+#[derive(Debug, Clone, Copy)]
+pub struct QueueStatus {
+    /// 1 for the player who waited longest
+    pub position: usize,
+    pub waiting: usize,
+    pub estimated_wait: std::time::Duration,
+}
+
+impl From<QueueStatus> for info::QueueInfo {
+    fn from(value: QueueStatus) -> Self {
+        info::QueueInfo {
+            position: value.position as u32,
+            waiting: value.waiting as u32,
+            estimatedwait: value.estimated_wait.as_secs(),
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct GameData {
+    /// given by the storage, 0 until the game is stored
+    #[serde(default)]
+    pub id: u64,
     pub result: GameResult,
+    #[serde(default)]
+    pub termination: Option<Termination>,
     pub black_user: UserData,
     pub white_user: UserData,
-    //pub time: TimeControl,
+    #[serde(default)]
+    pub time: Option<TimeControl>,
+    #[serde(default, with = "rule_name")]
+    pub rule: RuleSet,
+    #[serde(default = "default_board_size")]
+    pub board_size: usize,
+    #[serde(default)]
+    pub started_at: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub ended_at: Option<DateTime<Utc>>,
     pub notations: Vec<Notation>,
+    /// one per notation for games played on the server, empty otherwise
+    #[serde(default)]
+    pub move_times: Vec<MoveTime>,
+    /// whether the result changes the players' ratings
+    #[serde(default)]
+    pub rated: bool,
+    /// set when a rated game is stored
+    #[serde(default)]
+    pub rating_change: Option<RatingChange>,
+}
+
+fn default_board_size() -> usize {
+    8
+}
+
+/// [RuleSet] stored by its name
+mod rule_name {
+    use fir_game::RuleSet;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(rule: &RuleSet, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(rule)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<RuleSet, D::Error> {
+        let name = String::deserialize(deserializer)?;
+        name.parse().map_err(serde::de::Error::custom)
+    }
 }
 
 impl GameData {
+    /// game starting now
     pub fn new(black_user: UserData, white_user: UserData) -> Self {
         GameData {
+            id: 0,
             result: GameResult::OnGoing,
+            termination: None,
             black_user,
             white_user,
+            time: None,
+            rule: RuleSet::default(),
+            board_size: default_board_size(),
+            started_at: Some(Utc::now()),
+            ended_at: None,
             notations: vec![],
+            move_times: vec![],
+            rated: false,
+            rating_change: None,
         }
     }
-}
 
-impl Into<info::GameInfo> for GameData {
-    fn into(self) -> info::GameInfo {
-        let result = match self.result {
-            GameResult::Win(Side::White) => "white win",
-            GameResult::Win(Side::Black) => "black win",
-            GameResult::Resign(Side::White) => "white resign",
-            GameResult::Resign(Side::Black) => "black resign",
-            GameResult::Draw => "draw",
-            GameResult::Abort => "abort",
-            _ => unreachable!(),
+    /// record a move played now
+    pub fn play(&mut self, notation: Notation, clock_ms: Option<u64>) {
+        self.notations.push(notation);
+        self.move_times.push(MoveTime {
+            played_at: Utc::now(),
+            clock_ms,
+        });
+    }
+
+    /// remove the last `count` moves
+    pub fn take_back(&mut self, count: usize) {
+        let len = self.notations.len().saturating_sub(count);
+        self.notations.truncate(len);
+        self.move_times.truncate(len);
+    }
+
+    /// record the end of the game
+    pub fn finish(&mut self, result: GameResult, termination: Termination) {
+        self.result = result;
+        self.termination = Some(termination);
+        self.ended_at = Some(Utc::now());
+    }
+
+    /// time spent on each move in milliseconds, empty if unknown
+    pub fn move_durations_ms(&self) -> Vec<u32> {
+        let Some(mut last) = self.started_at else {
+            return vec![];
         };
+        if self.move_times.len() != self.notations.len() {
+            return vec![];
+        }
+        self.move_times
+            .iter()
+            .map(|time| {
+                let spent = (time.played_at - last).num_milliseconds().max(0);
+                last = time.played_at;
+                spent.min(u32::MAX as i64) as u32
+            })
+            .collect()
+    }
+
+    /// stored moves as board points
+    pub fn moves(&self) -> Vec<fir_game::Coord> {
+        self.notations.iter().map(|n| (*n).into()).collect()
+    }
+
+    /// stored game as a plain move list
+    pub fn to_record(&self) -> GameRecord {
+        GameRecord {
+            black: Some(self.black_user.record_name().to_string()),
+            white: Some(self.white_user.record_name().to_string()),
+            times_ms: self.move_durations_ms(),
+            ..GameRecord::new(self.board_size, self.rule, self.moves())
+        }
+    }
+
+    /// game from an imported record, players are kept by name only
+    pub fn from_record(record: GameRecord) -> Result<Self, fir_game::FirError> {
+        let game = record.replay()?;
+        let player = |name: Option<String>| match name {
+            Some(name) => UserData::imported(&name),
+            None => UserData::anonymous(),
+        };
+        let (result, termination) = match game.winning_line() {
+            Some((order, _)) => (GameResult::Win(order.into()), Some(Termination::Five)),
+            None => (GameResult::Unknown, None),
+        };
+        let notations = record
+            .moves
+            .iter()
+            .enumerate()
+            .map(|(i, coord)| Notation {
+                color: i % 2 == 0,
+                x: coord.x,
+                y: coord.y,
+            })
+            .collect();
+        Ok(GameData {
+            id: 0,
+            result,
+            termination,
+            black_user: player(record.black),
+            white_user: player(record.white),
+            time: None,
+            rule: record.rule,
+            board_size: record.size,
+            started_at: None,
+            ended_at: None,
+            notations,
+            move_times: vec![],
+            rated: false,
+            rating_change: None,
+        })
+    }
+
+    /// stored game as an sgf game tree
+    pub fn to_sgf(&self) -> SgfGame {
+        let mut game = SgfGame {
+            black_rating: Some(self.black_user.rating.to_string()),
+            white_rating: Some(self.white_user.rating.to_string()),
+            result: self.result.to_sgf(),
+            time_limit: self.time.map(|t| t.seconds),
+            overtime: self
+                .time
+                .filter(|t| t.fisher > 0)
+                .map(|t| format!("fischer {}", t.fisher)),
+            ..SgfGame::from_record(&self.to_record())
+        };
+        if let Some(started_at) = self.started_at {
+            let date = started_at.format("%Y-%m-%d").to_string();
+            game.root.properties.push((String::from("DT"), vec![date]));
+        }
+        game
+    }
+
+    /// game from the main line of an sgf game tree
+    pub fn from_sgf(game: SgfGame) -> Result<Self, fir_game::FirError> {
+        let mut data = GameData::from_record(game.to_record())?;
+        data.time = game
+            .time_limit
+            .map(|seconds| TimeControl::from_sgf(seconds, game.overtime.as_deref()));
+        if let Some(result) = game.result.as_deref().and_then(GameResult::from_sgf) {
+            data.result = result;
+        }
+        let rating = |r: &Option<String>| r.as_deref().and_then(|r| r.trim().parse().ok());
+        data.black_user.rating = rating(&game.black_rating).unwrap_or(0);
+        data.white_user.rating = rating(&game.white_rating).unwrap_or(0);
+        Ok(data)
+    }
+
+    /// rebuild the board from the stored moves
+    pub fn replay(&self) -> Result<fir_game::FirGame, fir_game::FirError> {
+        self.to_record().replay()
+    }
+
+    /// animate the stored moves
+    pub fn to_gif(&self, options: &fir_game::GifOptions) -> Result<Vec<u8>, fir_game::FirError> {
+        let record = self.to_record();
+        fir_game::game_to_gif(record.size, record.rule, &record.moves, options)
+    }
+}
+
+impl From<GameData> for info::GameInfo {
+    fn from(value: GameData) -> Self {
+        let time = |t: Option<DateTime<Utc>>| t.map(|t| t.to_rfc3339()).unwrap_or_default();
         info::GameInfo {
-            result: String::from(result),
-            blackname: self.black_user.id.clone(),
-            blackrating: self.black_user.rating,
-            whitename: self.white_user.id.clone(),
-            whiterating: self.white_user.rating,
-            notations: self.notations.into_iter().map(|n| n.into()).collect(),
+            id: value.id,
+            result: value.result.to_string(),
+            termination: value.termination.map(|t| t.to_string()).unwrap_or_default(),
+            rule: value.rule.to_string(),
+            timecontrol: value.time.map(|t| t.to_string()).unwrap_or_default(),
+            starttime: time(value.started_at),
+            endtime: time(value.ended_at),
+            blackname: value.black_user.id.clone(),
+            blackrating: value.black_user.rating,
+            rated: value.rated,
+            blackratingchange: value.rating_change.map_or(0, |r| r.black_change),
+            whiteratingchange: value.rating_change.map_or(0, |r| r.white_change),
+            whitename: value.white_user.id.clone(),
+            whiterating: value.white_user.rating,
+            notations: value.notations.into_iter().map(|n| n.into()).collect(),
         }
     }
 }
 
-#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum Side {
     White,
     Black,
 }
 
-impl Into<Order> for Side {
-    fn into(self) -> Order {
+impl Side {
+    /// the other side
+    pub fn opponent(self) -> Side {
+        match self {
+            Side::White => Side::Black,
+            Side::Black => Side::White,
+        }
+    }
+
+    /// 0 for black, 1 for white, as colors are sent to clients
+    pub fn index(self) -> usize {
         match self {
+            Side::Black => 0,
+            Side::White => 1,
+        }
+    }
+}
+
+impl From<Side> for Order {
+    fn from(value: Side) -> Self {
+        match value {
             Side::White => Order::White,
             Side::Black => Order::Black,
         }
     }
 }
 
-impl Into<Side> for Order {
-    fn into(self) -> Side {
-        match self {
+impl From<Order> for Side {
+    fn from(value: Order) -> Self {
+        match value {
             Order::White => Side::White,
             Order::Black => Side::Black,
         }
@@ -151,8 +679,8 @@ impl Into<Side> for Order {
 }
 
 #[derive(Debug, Clone)]
+/// command of a player, whose side is known from the socket it came from
 pub struct GameCommand {
-    pub side: Side,
     pub command_type: CommandType,
     pub notation: Notation,
     pub message: String,
@@ -164,12 +692,21 @@ pub enum CommandType {
     Resign,
     OfferDraw,
     AcceptDraw,
+    DeclineDraw,
+    RequestTakeback,
+    AcceptTakeback,
+    DeclineTakeback,
     Message,
+    /// leave the match queue
+    Cancel,
 }
 
-impl Into<GameCommand> for info::GameCommandInfo {
-    fn into(self) -> GameCommand {
-        let resp = self.command;
+/// fails with the name of an unknown command
+impl TryFrom<info::GameCommandInfo> for GameCommand {
+    type Error = String;
+
+    fn try_from(value: info::GameCommandInfo) -> Result<Self, Self::Error> {
+        let resp = value.command;
         let command_type = if resp == "Play" {
             CommandType::Play
         } else if resp == "Resign" {
@@ -178,68 +715,243 @@ impl Into<GameCommand> for info::GameCommandInfo {
             CommandType::OfferDraw
         } else if resp == "AcceptDraw" {
             CommandType::AcceptDraw
+        } else if resp == "DeclineDraw" {
+            CommandType::DeclineDraw
+        } else if resp == "RequestTakeback" {
+            CommandType::RequestTakeback
+        } else if resp == "AcceptTakeback" {
+            CommandType::AcceptTakeback
+        } else if resp == "DeclineTakeback" {
+            CommandType::DeclineTakeback
         } else if resp == "Message" {
             CommandType::Message
+        } else if resp == "Cancel" {
+            CommandType::Cancel
         } else {
-            unreachable!("Unkown Command")
+            return Err(resp);
         };
 
-        let side = if self.side == 0 {
-            Side::Black
-        } else {
-            Side::White
-        };
-
-        GameCommand {
+        Ok(GameCommand {
             command_type,
-            message: self.message,
-            notation: self.notation.into(),
-            side,
-        }
+            message: value.message,
+            notation: value.notation.into(),
+        })
     }
 }
 
 #[derive(Debug, Clone)]
 pub enum GameResponse {
-    Start(Side, String),
-    OpponentPlay(Notation),
+    Start(Side, String, Clocks),
+    OpponentPlay(Notation, Clocks),
+    /// clocks after the player's own move
+    Clock(Clocks),
+    /// side that ran out of time
+    Timeout(Side),
     OpponentResign,
     OpponentOfferDraw,
-    GameEnd,
+    OpponentAcceptDraw,
+    OpponentDeclineDraw,
+    OpponentRequestTakeback,
+    OpponentDeclineTakeback,
+    /// moves left after a takeback
+    Takeback(Vec<Notation>, Clocks),
+    OpponentDisconnected,
+    OpponentReconnected,
+    /// whole game for a player who connected again
+    Resync(Side, GameState),
+    /// whole game for a new spectator
+    Snapshot(GameState),
+    /// move shown to spectators
+    Move(Notation, Clocks),
+    /// number of spectators, shown to players
+    Spectators(usize),
+    /// end of the game shown to spectators
+    Result(GameResult, Termination, Option<RatingChange>),
+    GameEnd(Option<RatingChange>),
     Message(String),
+    QueueStatus(QueueStatus),
 }
 
-impl Into<info::GameResponseInfo> for GameResponse {
-    fn into(self) -> info::GameResponseInfo {
-        let command = match self {
-            GameResponse::Start(_, _) => "Start",
-            GameResponse::OpponentPlay(_) => "OpponentPlay",
+impl From<GameResponse> for info::GameResponseInfo {
+    fn from(value: GameResponse) -> Self {
+        let command = match value {
+            GameResponse::Start(_, _, _) => "Start",
+            GameResponse::OpponentPlay(_, _) => "OpponentPlay",
+            GameResponse::Clock(_) => "Clock",
+            GameResponse::Timeout(_) => "Timeout",
             GameResponse::OpponentResign => "OpponentResign",
             GameResponse::OpponentOfferDraw => "OpponentOfferDraw",
-            GameResponse::GameEnd => "GameEnd",
+            GameResponse::OpponentAcceptDraw => "OpponentAcceptDraw",
+            GameResponse::OpponentDeclineDraw => "OpponentDeclineDraw",
+            GameResponse::OpponentRequestTakeback => "OpponentRequestTakeback",
+            GameResponse::OpponentDeclineTakeback => "OpponentDeclineTakeback",
+            GameResponse::Takeback(_, _) => "Takeback",
+            GameResponse::OpponentDisconnected => "OpponentDisconnected",
+            GameResponse::OpponentReconnected => "OpponentReconnected",
+            GameResponse::Resync(_, _) => "Resync",
+            GameResponse::Snapshot(_) => "Snapshot",
+            GameResponse::Move(_, _) => "Move",
+            GameResponse::Spectators(_) => "Spectators",
+            GameResponse::Result(_, _, _) => "Result",
+            GameResponse::GameEnd(_) => "GameEnd",
             GameResponse::Message(_) => "Message",
+            GameResponse::QueueStatus(_) => "QueueStatus",
         };
-        let notation = match self {
-            GameResponse::Start(Side::Black, _) => NotationInfo {
+        let notation = match value {
+            GameResponse::Start(Side::Black, _, _)
+            | GameResponse::Timeout(Side::Black)
+            | GameResponse::Resync(Side::Black, _) => NotationInfo {
                 color: 0,
                 ..NotationInfo::default()
             },
-            GameResponse::Start(Side::White, _) => NotationInfo {
+            GameResponse::Start(Side::White, _, _)
+            | GameResponse::Timeout(Side::White)
+            | GameResponse::Resync(Side::White, _) => NotationInfo {
                 color: 1,
                 ..NotationInfo::default()
             },
-            GameResponse::OpponentPlay(n) => n.into(),
+            GameResponse::OpponentPlay(n, _) | GameResponse::Move(n, _) => n.into(),
             _ => NotationInfo::default(),
         };
-        let message = match self {
+        let clocks = match value {
+            GameResponse::Start(_, _, clocks)
+            | GameResponse::OpponentPlay(_, clocks)
+            | GameResponse::Move(_, clocks)
+            | GameResponse::Clock(clocks)
+            | GameResponse::Takeback(_, clocks) => Some(clocks.into()),
+            _ => None,
+        };
+        let moves = match &value {
+            GameResponse::Takeback(moves, _) => Some(moves.iter().map(|n| (*n).into()).collect()),
+            _ => None,
+        };
+        let ratings = match value {
+            GameResponse::GameEnd(ratings) | GameResponse::Result(_, _, ratings) => {
+                ratings.map(|r| r.into())
+            }
+            _ => None,
+        };
+        let queue = match value {
+            GameResponse::QueueStatus(status) => Some(status.into()),
+            _ => None,
+        };
+        let state = match &value {
+            GameResponse::Resync(_, state) | GameResponse::Snapshot(state) => {
+                Some(state.clone().into())
+            }
+            _ => None,
+        };
+        let spectators = match value {
+            GameResponse::Spectators(count) => Some(count as u32),
+            _ => None,
+        };
+        let message = match value {
             GameResponse::Message(s) => s,
-            GameResponse::Start(_, opp) => opp,
+            GameResponse::Result(result, termination, _) => format!("{result} ({termination})"),
+            GameResponse::Start(_, opp, _) => opp,
             _ => String::new(),
         };
         info::GameResponseInfo {
             command: String::from(command),
             notation,
             message,
+            ratings,
+            queue,
+            clocks,
+            moves,
+            state,
+            spectators,
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn game() -> GameData {
+        let mut game = GameData::new(
+            UserData::new(String::from("alice"), String::new(), 620),
+            UserData::new(String::from("bob"), String::new(), 580),
+        );
+        game.time = Some(TimeControl {
+            seconds: 180,
+            fisher: 2,
+        });
+        for (i, (x, y)) in [(3, 3), (4, 4), (3, 4)].into_iter().enumerate() {
+            let color = i % 2 == 0;
+            game.play(Notation { color, x, y }, None);
+        }
+        game.finish(GameResult::Resign(Side::White), Termination::Resign);
+        game
+    }
+
+    #[test]
+    fn debug_output_hides_the_password() {
+        let user = UserData::new(String::from("alice"), String::from("$argon2id$secret"), 0);
+        let game = GameData::new(user.clone(), UserData::anonymous());
+        assert!(!format!("{user:?}").contains("secret"));
+        assert!(!format!("{game:?}").contains("secret"));
+    }
+
+    #[test]
+    fn sgf_keeps_time_control_and_result() {
+        let sgf = game().to_sgf().to_sgf();
+        assert!(sgf.contains("TM[180]"));
+        assert!(sgf.contains("OT[fischer 2]"));
+
+        let imported = GameData::from_sgf(SgfGame::parse(&sgf).unwrap()).unwrap();
+        assert_eq!(
+            imported.time,
+            Some(TimeControl {
+                seconds: 180,
+                fisher: 2
+            })
+        );
+        assert!(matches!(imported.result, GameResult::Resign(Side::White)));
+        assert_eq!(imported.black_user.rating, 620);
+        assert_eq!(imported.notations.len(), 3);
+    }
+
+    #[test]
+    fn imported_game_without_five_has_unknown_result() {
+        let record = GameRecord {
+            black: Some(String::from("alice")),
+            white: Some(String::from("bob")),
+            ..GameRecord::new(15, RuleSet::Freestyle, vec![fir_game::Coord { x: 7, y: 7 }])
+        };
+        let imported = GameData::from_record(record).unwrap();
+        assert!(matches!(imported.result, GameResult::Unknown));
+        assert_eq!(imported.termination, None);
+        assert_eq!(imported.to_sgf().result.as_deref(), Some("?"));
+        assert!(matches!(
+            GameResult::from_sgf("?"),
+            Some(GameResult::Unknown)
+        ));
+    }
+
+    #[test]
+    fn imported_players_are_kept_apart_from_accounts() {
+        let sgf = game().to_sgf().to_sgf();
+        let imported = GameData::from_sgf(SgfGame::parse(&sgf).unwrap()).unwrap();
+        assert_eq!(imported.black_user.id, "import:alice");
+        assert_eq!(imported.white_user.id, "import:bob");
+
+        let exported = imported.to_record();
+        assert_eq!(exported.black.as_deref(), Some("alice"));
+        assert_eq!(exported.white.as_deref(), Some("bob"));
+    }
+
+    #[test]
+    fn sgf_time_control_without_increment() {
+        assert_eq!(
+            TimeControl::from_sgf(600, None),
+            TimeControl {
+                seconds: 600,
+                fisher: 0
+            }
+        );
+        assert_eq!(TimeControl::from_sgf(600, Some("Fischer 5")).fisher, 5);
+        assert_eq!(TimeControl::from_sgf(600, Some("5x30 byo-yomi")).fisher, 0);
+    }
+}
@@ -12,6 +12,17 @@ pub struct RegisterInfo {
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct UserKeyInfo {
     pub key: String,
+    /// RFC 3339 time the key stops working, empty in requests
+    #[serde(default)]
+    pub expiretime: String,
+}
+
+/// error body of a failed request
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ErrorInfo {
+    /// stable name such as `duplicate_id`
+    pub code: String,
+    pub message: String,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -23,9 +34,33 @@ pub struct LoginInfo {
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct UserInfo {
     pub id: String,
-    pub pwd: String,
     pub rating: u32,
+    /// rated games played
+    #[serde(default)]
+    pub games: u32,
+    /// rating is still settling
+    #[serde(default)]
+    pub provisional: bool,
+    /// Glicko-2 rating deviation
+    #[serde(default)]
+    pub deviation: u32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LogoutInfo {
     pub key: String,
+    /// id of another session of the same user to end, empty for this one
+    #[serde(default)]
+    pub session: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SessionInfo {
+    pub id: String,
+    pub createtime: String,
+    pub expiretime: String,
+    /// the session of the key used to ask
+    pub current: bool,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -47,16 +82,39 @@ impl Default for NotationInfo {
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct GameInfo {
+    #[serde(default)]
+    pub id: u64,
     pub result: String,
+    /// five, resign, timeout, disconnect or draw agreement
+    #[serde(default)]
+    pub termination: String,
+    #[serde(default)]
+    pub rule: String,
+    /// `seconds+fisher`, empty without a time control
+    #[serde(default)]
+    pub timecontrol: String,
+    /// RFC 3339 times, empty if unknown
+    #[serde(default)]
+    pub starttime: String,
+    #[serde(default)]
+    pub endtime: String,
     pub blackname: String,
     pub blackrating: u32,
     pub whitename: String,
     pub whiterating: u32,
+    #[serde(default)]
+    pub rated: bool,
+    #[serde(default)]
+    pub blackratingchange: i32,
+    #[serde(default)]
+    pub whiteratingchange: i32,
     pub notations: Vec<NotationInfo>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct GameCommandInfo {
+    /// not needed, the server knows the side of every socket
+    #[serde(default)]
     pub side: u32,
     pub command: String,
     pub notation: NotationInfo,
@@ -68,4 +126,93 @@ pub struct GameResponseInfo {
     pub command: String,
     pub notation: NotationInfo,
     pub message: String,
+    /// new ratings, sent with the end of a rated game
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ratings: Option<RatingInfo>,
+    /// place in the match queue, sent while waiting for an opponent
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub queue: Option<QueueInfo>,
+    /// time left of both players, sent with the start and every move
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub clocks: Option<ClockInfo>,
+    /// every move of the game, sent when the position changes other than by a move
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub moves: Option<Vec<NotationInfo>>,
+    /// whole game, sent to a player who connected again
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub state: Option<GameStateInfo>,
+    /// people watching the game, sent to the players when it changes
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub spectators: Option<u32>,
+}
+
+/// game in progress that can be watched
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LiveGameInfo {
+    pub id: u64,
+    pub black: String,
+    pub white: String,
+    pub rule: String,
+    pub timecontrol: String,
+    pub starttime: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GameStateInfo {
+    pub black: String,
+    pub white: String,
+    pub rule: String,
+    pub timecontrol: String,
+    /// color to move
+    pub turn: u32,
+    /// rows from the top, `X` black, `0` white and `*` empty
+    pub board: Vec<String>,
+    pub moves: Vec<NotationInfo>,
+    pub clocks: ClockInfo,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RatingInfo {
+    pub black: u32,
+    pub blackchange: i32,
+    pub white: u32,
+    pub whitechange: i32,
+}
+
+/// milliseconds
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ClockInfo {
+    pub black: u64,
+    pub white: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct QueueInfo {
+    /// 1 for the player who waited longest
+    pub position: u32,
+    /// players waiting with the same settings
+    pub waiting: u32,
+    /// seconds
+    pub estimatedwait: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AnalyzeInfo {
+    pub notations: Vec<NotationInfo>,
+    pub depth: u32,
+    pub count: u32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SuggestionInfo {
+    pub x: u32,
+    pub y: u32,
+    pub score: i32,
+    pub pv: Vec<NotationInfo>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AnalysisInfo {
+    pub score: i32,
+    pub suggestions: Vec<SuggestionInfo>,
 }
@@ -0,0 +1,383 @@
+//! Append-only journal with snapshots
+//!
+//! Every change is appended to `journal.jsonl` as one numbered JSON line. On
+//! startup `snapshot.json` is loaded, the journal is replayed on top of it, and
+//! the result becomes the new snapshot so the journal starts empty again. A
+//! snapshot records the last line it holds, so lines left behind by a crash
+//! before the journal was emptied are not applied twice.
+
+use super::data::{GameData, UserData};
+use super::password;
+use super::session::Session;
+use super::storage::{MemoryStorage, Storage};
+use crate::prelude::*;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+
+const SNAPSHOT: &str = "snapshot.json";
+const JOURNAL: &str = "journal.jsonl";
+/// journal entries written before the next snapshot
+const SNAPSHOT_INTERVAL: usize = 1000;
+
+/// one change to the stored data
+#[derive(Debug, Clone, Serialize, Deserialize)]
+enum Entry {
+    User(UserData),
+    Game(Box<GameData>),
+    Session(Session),
+    /// session key
+    Logout(String),
+    /// sessions expired at the time
+    Expire(DateTime<Utc>),
+}
+
+/// one journal line
+///
+/// lines written before they were numbered have no `seq` and are always
+/// applied.
+#[derive(Debug, Serialize, Deserialize)]
+struct Line<E> {
+    #[serde(default)]
+    seq: Option<u64>,
+    #[serde(flatten)]
+    entry: E,
+}
+
+/// contents of the snapshot file, holding the journal up to line `seq`
+#[derive(Debug, Serialize, Deserialize)]
+struct Snapshot<S> {
+    #[serde(default)]
+    seq: u64,
+    #[serde(flatten)]
+    storage: S,
+}
+
+impl Entry {
+    fn apply(self, storage: &mut MemoryStorage) {
+        match self {
+            Entry::User(user) => storage.put_user(user),
+            Entry::Game(game) => {
+                storage.add_game(*game);
+            }
+            Entry::Session(session) => storage.add_session(session),
+            Entry::Logout(key) => {
+                storage.remove_session(&key);
+            }
+            Entry::Expire(now) => {
+                storage.expire_sessions(now);
+            }
+        }
+    }
+}
+
+struct Journal {
+    dir: PathBuf,
+    file: File,
+    /// number of the last line written
+    seq: u64,
+    entries: usize,
+}
+
+impl Journal {
+    /// open the data directory and load what was stored there
+    fn open(dir: &Path) -> io::Result<(Journal, MemoryStorage)> {
+        fs::create_dir_all(dir)?;
+
+        let snapshot: Snapshot<MemoryStorage> = match fs::read_to_string(dir.join(SNAPSHOT)) {
+            Ok(text) => serde_json::from_str(&text)?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Snapshot {
+                seq: 0,
+                storage: MemoryStorage::default(),
+            },
+            Err(e) => return Err(e),
+        };
+        let mut storage = snapshot.storage;
+        let mut seq = snapshot.seq;
+
+        storage.reindex();
+
+        let journal_path = dir.join(JOURNAL);
+        if journal_path.exists() {
+            let reader = BufReader::new(File::open(&journal_path)?);
+            for (number, line) in reader.lines().enumerate() {
+                let line = line?;
+                if line.trim().is_empty() {
+                    continue;
+                }
+                match serde_json::from_str::<Line<Entry>>(&line) {
+                    // already in the snapshot
+                    Ok(Line { seq: Some(n), .. }) if n <= snapshot.seq => (),
+                    Ok(line) => {
+                        seq = seq.max(line.seq.unwrap_or(0));
+                        line.entry.apply(&mut storage);
+                    }
+                    // a crash while writing leaves a partial last line
+                    Err(e) => log(&format!("skip broken journal line {}: {e}", number + 1)),
+                }
+            }
+        }
+
+        password::migrate(&mut storage);
+        storage.expire_sessions(Utc::now());
+
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&journal_path)?;
+        let mut journal = Journal {
+            dir: dir.to_path_buf(),
+            file,
+            seq,
+            entries: 0,
+        };
+        journal.snapshot(&storage)?;
+        log(&format!(
+            "load {} users and {} games from {}",
+            storage.users().len(),
+            storage.games().len(),
+            dir.display()
+        ));
+        Ok((journal, storage))
+    }
+
+    /// write one change to disk
+    ///
+    /// returns true once enough entries piled up to take a snapshot.
+    fn append(&mut self, entry: &Entry) -> io::Result<bool> {
+        let line = Line {
+            seq: Some(self.seq + 1),
+            entry,
+        };
+        let line = serde_json::to_string(&line)?;
+        writeln!(self.file, "{line}")?;
+        self.file.sync_data()?;
+        self.seq += 1;
+        self.entries += 1;
+        Ok(self.entries >= SNAPSHOT_INTERVAL)
+    }
+
+    /// store everything in a fresh snapshot and empty the journal
+    ///
+    /// line numbers go on after the snapshot, so the journal is only emptied
+    /// once the snapshot is safely in place.
+    fn snapshot(&mut self, storage: &MemoryStorage) -> io::Result<()> {
+        let tmp = self.dir.join(format!("{SNAPSHOT}.tmp"));
+        {
+            let snapshot = Snapshot {
+                seq: self.seq,
+                storage,
+            };
+            let mut file = File::create(&tmp)?;
+            file.write_all(serde_json::to_string(&snapshot)?.as_bytes())?;
+            file.sync_all()?;
+        }
+        fs::rename(&tmp, self.dir.join(SNAPSHOT))?;
+        sync_dir(&self.dir)?;
+        self.file.set_len(0)?;
+        self.file.sync_all()?;
+        self.entries = 0;
+        Ok(())
+    }
+}
+
+/// make a rename in `dir` survive a crash
+fn sync_dir(dir: &Path) -> io::Result<()> {
+    // only unix lets a directory be opened like a file
+    #[cfg(unix)]
+    File::open(dir)?.sync_all()?;
+    #[cfg(not(unix))]
+    let _ = dir;
+    Ok(())
+}
+
+/// data kept in memory, with every change written to the journal
+pub struct JournalStorage {
+    memory: MemoryStorage,
+    journal: Journal,
+}
+
+impl JournalStorage {
+    pub fn open(dir: &Path) -> io::Result<Self> {
+        let (journal, memory) = Journal::open(dir)?;
+        Ok(JournalStorage { memory, journal })
+    }
+
+    fn persist(&mut self, entry: Entry) {
+        match self.journal.append(&entry) {
+            Ok(false) => (),
+            Ok(true) => {
+                if let Err(e) = self.journal.snapshot(&self.memory) {
+                    log(&format!("fail to write snapshot: {e}"));
+                }
+            }
+            Err(e) => log(&format!("fail to write journal: {e}")),
+        }
+    }
+}
+
+impl Storage for JournalStorage {
+    fn add_user(&mut self, user: UserData) -> bool {
+        if !self.memory.add_user(user.clone()) {
+            return false;
+        }
+        self.persist(Entry::User(user));
+        true
+    }
+
+    fn put_user(&mut self, user: UserData) {
+        self.memory.put_user(user.clone());
+        self.persist(Entry::User(user));
+    }
+
+    fn user(&self, id: &str) -> Option<&UserData> {
+        self.memory.user(id)
+    }
+
+    fn users(&self) -> &[UserData] {
+        self.memory.users()
+    }
+
+    fn add_session(&mut self, session: Session) {
+        self.memory.add_session(session.clone());
+        self.persist(Entry::Session(session));
+    }
+
+    fn session(&self, key: &str) -> Option<&Session> {
+        self.memory.session(key)
+    }
+
+    fn remove_session(&mut self, key: &str) -> bool {
+        if !self.memory.remove_session(key) {
+            return false;
+        }
+        self.persist(Entry::Logout(key.to_string()));
+        true
+    }
+
+    fn user_sessions(&self, id: &str) -> Vec<&Session> {
+        self.memory.user_sessions(id)
+    }
+
+    fn expire_sessions(&mut self, now: DateTime<Utc>) -> usize {
+        let count = self.memory.expire_sessions(now);
+        if count > 0 {
+            self.persist(Entry::Expire(now));
+        }
+        count
+    }
+
+    fn add_game(&mut self, game: GameData) -> u64 {
+        let id = self.memory.add_game(game);
+        // the stored copy carries the id
+        if let Some(game) = self.memory.game(id) {
+            self.persist(Entry::Game(Box::new(game.clone())));
+        }
+        id
+    }
+
+    fn reserve_game_id(&mut self) -> u64 {
+        // a game is written once it is stored under the id
+        self.memory.reserve_game_id()
+    }
+
+    fn game(&self, id: u64) -> Option<&GameData> {
+        self.memory.game(id)
+    }
+
+    fn games(&self) -> &[GameData] {
+        self.memory.games()
+    }
+
+    fn user_games(&self, id: &str) -> Vec<&GameData> {
+        self.memory.user_games(id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// empty directory for one test
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("fir-journal-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn user(id: &str, rating: u32) -> UserData {
+        UserData::new(String::from(id), String::new(), rating)
+    }
+
+    #[test]
+    fn reopen_keeps_changes() {
+        let dir = temp_dir("reopen");
+        {
+            let mut storage = JournalStorage::open(&dir).unwrap();
+            assert!(storage.add_user(user("alice", 600)));
+            assert!(storage.add_user(user("bob", 600)));
+            assert!(!storage.add_user(user("bob", 900)));
+            storage.put_user(user("alice", 650));
+            storage.add_game(GameData::new(user("alice", 650), user("bob", 600)));
+        }
+        let storage = JournalStorage::open(&dir).unwrap();
+        assert_eq!(storage.users().len(), 2);
+        assert_eq!(storage.user("alice").unwrap().rating, 650);
+        assert_eq!(storage.games().len(), 1);
+        assert_eq!(storage.user_games("bob").len(), 1);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn reopen_after_snapshot_skips_stale_journal() {
+        let dir = temp_dir("snapshot");
+        let stale = {
+            let mut storage = JournalStorage::open(&dir).unwrap();
+            storage.add_user(user("alice", 600));
+            storage.add_game(GameData::new(user("alice", 600), user("alice", 600)));
+            let stale = fs::read_to_string(dir.join(JOURNAL)).unwrap();
+            storage.journal.snapshot(&storage.memory).unwrap();
+            stale
+        };
+        // a crash after the snapshot was renamed but before the journal was
+        // emptied, with a line the snapshot holds already and one it does not
+        let held = Line {
+            seq: Some(2),
+            entry: Entry::User(user("alice", 100)),
+        };
+        let late = Line {
+            seq: Some(3),
+            entry: Entry::User(user("bob", 600)),
+        };
+        let journal = [&stale, &serde_json::to_string(&held).unwrap(), "\n"].concat()
+            + &serde_json::to_string(&late).unwrap()
+            + "\n";
+        fs::write(dir.join(JOURNAL), journal).unwrap();
+
+        {
+            let mut storage = JournalStorage::open(&dir).unwrap();
+            assert_eq!(storage.users().len(), 2);
+            assert_eq!(storage.games().len(), 1);
+            assert_eq!(storage.user("alice").unwrap().rating, 600);
+            // numbering goes on across restarts
+            storage.put_user(user("alice", 750));
+        }
+        let storage = JournalStorage::open(&dir).unwrap();
+        assert_eq!(storage.user("alice").unwrap().rating, 750);
+        assert_eq!(storage.games().len(), 1);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn unnumbered_lines_are_applied() {
+        let dir = temp_dir("legacy");
+        fs::create_dir_all(&dir).unwrap();
+        let entry = serde_json::to_string(&Entry::User(user("alice", 600))).unwrap();
+        fs::write(dir.join(JOURNAL), format!("{entry}\n")).unwrap();
+        let storage = JournalStorage::open(&dir).unwrap();
+        assert_eq!(storage.users().len(), 1);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
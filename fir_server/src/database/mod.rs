@@ -1,157 +1,367 @@
 pub mod data;
 pub mod info;
+mod journal;
+mod password;
+mod register;
+mod session;
+mod storage;
 
+use crate::config::Config;
 use crate::prelude::*;
+use crate::rating;
+pub use register::RegisterError;
+use session::Session;
 use std::sync::Arc;
+use storage::Storage;
 use tokio::sync::{
     mpsc::{Receiver, Sender},
-    Mutex,
+    oneshot, RwLock,
 };
 
+/// rating of a new user
+const START_RATING: u32 = 600;
+
 /**
  * Save game play data
  */
-
 pub struct Database {
-    users: Vec<data::UserData>,
-    games: Vec<data::GameData>,
+    storage: Box<dyn Storage>,
+    /// how long a login lasts
+    session_ttl: chrono::Duration,
+    rating_system: rating::RatingSystem,
 }
 
 impl Database {
-    fn new() -> Self {
-        Database {
-            users: vec![],
-            games: vec![],
+    /// create an account and log it in
+    ///
+    /// the password is hashed before the database is locked for writing.
+    pub async fn register_user(
+        db: &RwLock<Database>,
+        s: info::RegisterInfo,
+    ) -> Result<info::UserKeyInfo, RegisterError> {
+        register::check(&s)?;
+        // spare the hashing for a taken id, the insert checks again
+        if db.read().await.storage.user(&s.id).is_some() {
+            return Err(RegisterError::DuplicateId(s.id));
         }
+        let pwd = password::hash_blocking(s.pwd).await;
+        let mut data = db.write().await;
+        if !data.add_user_data(data::UserData::new(s.id.clone(), pwd, START_RATING)) {
+            return Err(RegisterError::DuplicateId(s.id));
+        }
+        Ok(data.new_session(&s.id))
     }
 
-    pub fn register_user(&mut self, s: info::RegisterInfo) -> info::UserKeyInfo {
-        let key = s.id.clone() + "_key";
-        let user = data::UserData {
-            id: s.id.clone(),
-            pwd: s.pwd,
-            rating: 600,
-            key: key.clone(),
-        };
-        self.add_user_data(user);
-        info::UserKeyInfo { key: key }
+    fn new_session(&mut self, id: &str) -> info::UserKeyInfo {
+        let session = Session::new(id, self.session_ttl);
+        let key = (&session).into();
+        self.storage.add_session(session);
+        key
     }
 
-    pub fn add_user_data(&mut self, data: data::UserData) {
-        log(&format!("add user data {:?}", data));
-        self.users.push(data);
+    /// session of the key, if it is still valid
+    fn valid_session(&self, key: &str) -> Option<&Session> {
+        self.storage
+            .session(key)
+            .filter(|session| !session.is_expired(chrono::Utc::now()))
     }
 
-    pub fn add_game_result(&mut self, data: data::GameData) {
+    /// store a new user whose password is hashed already
+    ///
+    /// returns false and keeps the stored account if the id is taken.
+    fn add_user_data(&mut self, data: data::UserData) -> bool {
+        log(&format!("add user data {}", data.id));
+        self.storage.add_user(data)
+    }
+
+    /// store a finished game, updating ratings if it is rated
+    pub fn add_game_result(&mut self, mut data: data::GameData) -> Option<data::RatingChange> {
+        // players are kept for their names and ratings only
+        data.black_user.pwd.clear();
+        data.white_user.pwd.clear();
+        if data.rated {
+            data.rating_change = self.rate(&mut data);
+        }
         log(&format!("add game result {:?}", data));
-        self.games.push(data);
+        let change = data.rating_change;
+        let id = self.storage.add_game(data);
+        log(&format!("stored game {id}"));
+        change
     }
 
-    pub fn try_login(&self, info: &info::LoginInfo) -> Option<data::UserData> {
-        for user in self.users.iter() {
-            if user.id == info.id && user.pwd == info.pwd {
-                return Some(user.clone());
+    /// apply the result of a rated game to both players
+    ///
+    /// the players stored with the game get the ratings they had before it.
+    fn rate(&mut self, game: &mut data::GameData) -> Option<data::RatingChange> {
+        let score = match game.result {
+            data::GameResult::Win(data::Side::Black)
+            | data::GameResult::Resign(data::Side::White) => 1.0,
+            data::GameResult::Win(data::Side::White)
+            | data::GameResult::Resign(data::Side::Black) => 0.0,
+            data::GameResult::Draw => 0.5,
+            data::GameResult::Abort | data::GameResult::OnGoing | data::GameResult::Unknown => {
+                return None
             }
+        };
+        let mut black = self.storage.user(&game.black_user.id)?.clone();
+        let mut white = self.storage.user(&game.white_user.id)?.clone();
+        if black.id == white.id {
+            return None;
         }
-        None
-    }
 
-    pub fn get_user(&self, key: &info::UserKeyInfo) -> Option<data::UserData> {
-        for info in self.users.iter() {
-            if info.key == key.key {
-                return Some(info.clone());
+        game.black_user.rating = black.rating;
+        game.white_user.rating = white.rating;
+        let (black_before, white_before) = (black.rating, white.rating);
+
+        match self.rating_system {
+            rating::RatingSystem::Elo => {
+                let (black_rating, white_rating) =
+                    rating::elo(black.rating_player(), white.rating_player(), score);
+                black.rating = black_rating;
+                white.rating = white_rating;
+            }
+            rating::RatingSystem::Glicko2 => {
+                let now = game.ended_at.unwrap_or_else(chrono::Utc::now);
+                let (black_player, white_player) = (black.glicko(now), white.glicko(now));
+                black.set_glicko(black_player.update(&[(white_player, score)]), now);
+                white.set_glicko(white_player.update(&[(black_player, 1.0 - score)]), now);
             }
         }
-        None
+        let change = data::RatingChange {
+            black: black.rating,
+            black_change: black.rating as i32 - black_before as i32,
+            white: white.rating,
+            white_change: white.rating as i32 - white_before as i32,
+        };
+
+        log(&format!(
+            "rating {} {} -> {}, {} {} -> {}",
+            black.id, black_before, black.rating, white.id, white_before, white.rating
+        ));
+        black.games += 1;
+        white.games += 1;
+        self.storage.put_user(black);
+        self.storage.put_user(white);
+        Some(change)
+    }
+
+    /// start a new session if the password is right
+    ///
+    /// the password is checked without holding the database lock.
+    pub async fn try_login(
+        db: &RwLock<Database>,
+        info: &info::LoginInfo,
+    ) -> Option<info::UserKeyInfo> {
+        let hash = db
+            .read()
+            .await
+            .storage
+            .user(&info.id)
+            .map(|u| u.pwd.clone());
+        if !password::verify_user_blocking(info.pwd.clone(), hash).await {
+            return None;
+        }
+        Some(db.write().await.new_session(&info.id))
+    }
+
+    /// replace a valid session with a new one
+    pub fn refresh_session(&mut self, key: &info::UserKeyInfo) -> Option<info::UserKeyInfo> {
+        let id = self.valid_session(&key.key)?.user.clone();
+        self.storage.remove_session(&key.key);
+        Some(self.new_session(&id))
+    }
+
+    /// end the session named in `info`, or the session of the key itself
+    pub fn logout(&mut self, info: &info::LogoutInfo) -> bool {
+        let Some(current) = self.valid_session(&info.key) else {
+            return false;
+        };
+        if info.session.is_empty() {
+            return self.storage.remove_session(&info.key);
+        }
+        let target = self
+            .storage
+            .user_sessions(&current.user)
+            .into_iter()
+            .find(|session| session.id() == info.session)
+            .map(|session| session.key.clone());
+        match target {
+            Some(target) => self.storage.remove_session(&target),
+            None => false,
+        }
+    }
+
+    /// active sessions of the user owning the key
+    pub fn get_sessions(&self, key: &info::UserKeyInfo) -> Option<Vec<info::SessionInfo>> {
+        let current = self.valid_session(&key.key)?;
+        let now = chrono::Utc::now();
+        Some(
+            self.storage
+                .user_sessions(&current.user)
+                .into_iter()
+                .filter(|session| !session.is_expired(now))
+                .map(|session| info::SessionInfo {
+                    id: session.id().to_string(),
+                    createtime: session.created_at.to_rfc3339(),
+                    expiretime: session.expires_at.to_rfc3339(),
+                    current: session.key == current.key,
+                })
+                .collect(),
+        )
+    }
+
+    pub fn expire_sessions(&mut self) {
+        let count = self.storage.expire_sessions(chrono::Utc::now());
+        if count > 0 {
+            log(&format!("expire {count} sessions"));
+        }
+    }
+
+    pub fn get_user(&self, key: &info::UserKeyInfo) -> Option<data::UserData> {
+        let session = self.valid_session(&key.key)?;
+        self.storage.user(&session.user).cloned()
     }
 
     pub fn get_user_game(&self, key: &info::UserKeyInfo) -> Vec<info::GameInfo> {
-        self.games
-            .iter()
-            .filter(|game| game.black_user.key == key.key || game.white_user.key == key.key)
+        let Some(session) = self.valid_session(&key.key) else {
+            return vec![];
+        };
+        self.storage
+            .user_games(&session.user)
+            .into_iter()
             .map(|game| game.clone().into())
             .collect()
     }
 
-    pub fn get_all_user(&self) -> &Vec<data::UserData> {
-        &self.users
+    pub fn get_game(&self, id: u64) -> Option<&data::GameData> {
+        self.storage.game(id)
+    }
+
+    pub fn get_all_user(&self) -> &[data::UserData] {
+        self.storage.users()
     }
 
-    pub fn get_all_game(&self) -> &Vec<data::GameData> {
-        &self.games
+    pub fn get_all_game(&self) -> &[data::GameData] {
+        self.storage.games()
     }
 
     pub fn get_all_game_serialize(
         &self,
     ) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
-        let str = serde_json::to_string(&self.games)?;
+        let games: Vec<info::GameInfo> = self
+            .storage
+            .games()
+            .iter()
+            .map(|game| game.clone().into())
+            .collect();
+        let str = serde_json::to_string(&games)?;
         Ok(str)
     }
 
     pub fn get_all_user_serizlie(
         &self,
     ) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
-        let str = serde_json::to_string(&self.users)?;
+        let users: Vec<info::UserInfo> = self
+            .storage
+            .users()
+            .iter()
+            .map(|user| user.clone().into())
+            .collect();
+        let str = serde_json::to_string(&users)?;
         Ok(str)
     }
 }
 
 #[derive(Debug)]
 pub enum UpdateQuery {
-    NewUser(info::RegisterInfo),
     UserData(data::UserData),
     GameData(data::GameData),
+    /// id for a game about to start, kept when it is stored
+    ReserveGameId(oneshot::Sender<u64>),
+    /// game played on the server, answered with the rating change
+    FinishedGame(data::GameData, oneshot::Sender<Option<data::RatingChange>>),
 }
 
 pub struct DataManager {
-    data: Arc<Mutex<Database>>,
+    data: Arc<RwLock<Database>>,
     sender: Sender<UpdateQuery>,
     receiver: Receiver<UpdateQuery>,
 }
 
 pub type DbSender = Sender<UpdateQuery>;
 
+/// how often expired sessions are dropped
+const SESSION_EXPIRE_INTERVAL: std::time::Duration = std::time::Duration::from_secs(60 * 60);
+
 impl DataManager {
-    pub fn new() -> Self {
+    /// data manager with the storage selected by `config`
+    pub fn open(config: &Config) -> std::io::Result<Self> {
         let (s, r) = tokio::sync::mpsc::channel(10);
+        let database = Database {
+            storage: storage::open(config)?,
+            session_ttl: config.session_ttl,
+            rating_system: config.rating,
+        };
 
-        DataManager {
-            data: Arc::new(Mutex::new(Database::new())),
+        Ok(DataManager {
+            data: Arc::new(RwLock::new(database)),
             sender: s,
             receiver: r,
-        }
+        })
     }
 
     pub fn get_sender(&self) -> Sender<UpdateQuery> {
         Sender::clone(&self.sender)
     }
 
-    pub fn get_db(&self) -> Arc<Mutex<Database>> {
+    pub fn get_db(&self) -> Arc<RwLock<Database>> {
         Arc::clone(&self.data)
     }
 
     pub async fn run(mut self) {
         log("db start!");
 
-        while let Some(value) = self.receiver.recv().await {
-            let mut data = self.data.lock().await;
+        let mut expire = tokio::time::interval(SESSION_EXPIRE_INTERVAL);
+        loop {
+            let value = tokio::select! {
+                value = self.receiver.recv() => value,
+                _ = expire.tick() => {
+                    self.data.write().await.expire_sessions();
+                    continue;
+                }
+            };
+            let Some(value) = value else {
+                break;
+            };
 
             match value {
                 UpdateQuery::GameData(s) => {
-                    data.add_game_result(s);
+                    self.data.write().await.add_game_result(s);
                 }
-                UpdateQuery::UserData(s) => {
-                    data.add_user_data(s);
+                UpdateQuery::ReserveGameId(reply) => {
+                    let id = self.data.write().await.storage.reserve_game_id();
+                    let _ = reply.send(id);
                 }
-                UpdateQuery::NewUser(s) => {
-                    data.add_user_data(data::UserData {
-                        id: s.id.clone(),
-                        pwd: s.pwd,
-                        rating: 600,
-                        key: s.id.clone() + "_key",
-                    });
+                UpdateQuery::FinishedGame(s, reply) => {
+                    let change = self.data.write().await.add_game_result(s);
+                    // the game room may be gone already
+                    let _ = reply.send(change);
+                }
+                UpdateQuery::UserData(s) => {
+                    self.add_user(s).await;
                 }
             }
         }
     }
+
+    /// store a new user, hashing a plaintext password before taking the lock
+    async fn add_user(&self, mut user: data::UserData) {
+        if !password::is_hash(&user.pwd) {
+            user.pwd = password::hash_blocking(user.pwd).await;
+        }
+        let id = user.id.clone();
+        if !self.data.write().await.add_user_data(user) {
+            log(&format!("user {id} already exists"));
+        }
+    }
 }
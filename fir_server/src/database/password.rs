@@ -0,0 +1,104 @@
+//! Password hashing
+//!
+//! Passwords are stored as argon2id hashes in the PHC string format, such as
+//! `$argon2id$v=19$m=19456,t=2,p=1$<salt>$<hash>`, which carries its own
+//! salt and parameters.
+
+use argon2::password_hash::rand_core::OsRng;
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::Argon2;
+use std::sync::OnceLock;
+
+use super::storage::Storage;
+use crate::prelude::*;
+
+/// hash `pwd` with a fresh random salt
+pub fn hash(pwd: &str) -> String {
+    let salt = SaltString::generate(&mut OsRng);
+    Argon2::default()
+        .hash_password(pwd.as_bytes(), &salt)
+        .expect("argon2 with default parameters")
+        .to_string()
+}
+
+/// check `pwd` against a stored hash
+pub fn verify(pwd: &str, hash: &str) -> bool {
+    match PasswordHash::new(hash) {
+        Ok(hash) => Argon2::default()
+            .verify_password(pwd.as_bytes(), &hash)
+            .is_ok(),
+        Err(_) => false,
+    }
+}
+
+/// hash to check passwords of unknown ids against, so a failed login takes
+/// as long whether or not the id exists
+fn dummy_hash() -> &'static str {
+    static DUMMY: OnceLock<String> = OnceLock::new();
+    DUMMY.get_or_init(|| hash("not the password of any account"))
+}
+
+/// [hash] on the blocking pool, argon2 is too slow for the async workers
+pub async fn hash_blocking(pwd: String) -> String {
+    tokio::task::spawn_blocking(move || hash(&pwd))
+        .await
+        .unwrap()
+}
+
+/// [verify] on the blocking pool against the stored hash of a user, if any
+///
+/// without a stored hash the password is still checked against a dummy hash
+/// and rejected.
+pub async fn verify_user_blocking(pwd: String, hash: Option<String>) -> bool {
+    tokio::task::spawn_blocking(move || match hash {
+        Some(hash) => verify(&pwd, &hash),
+        None => {
+            verify(&pwd, dummy_hash());
+            false
+        }
+    })
+    .await
+    .unwrap()
+}
+
+/// whether `stored` is a hash rather than a plaintext password
+pub fn is_hash(stored: &str) -> bool {
+    stored.starts_with("$argon2") && PasswordHash::new(stored).is_ok()
+}
+
+/// hash plaintext passwords left by older versions
+///
+/// players stored with games keep their names and ratings only, so their
+/// passwords are dropped.
+pub fn migrate(storage: &mut dyn Storage) {
+    let users: Vec<_> = storage
+        .users()
+        .iter()
+        .filter(|user| !is_hash(&user.pwd))
+        .cloned()
+        .collect();
+    let games: Vec<_> = storage
+        .games()
+        .iter()
+        .filter(|game| !game.black_user.pwd.is_empty() || !game.white_user.pwd.is_empty())
+        .cloned()
+        .collect();
+    if users.is_empty() && games.is_empty() {
+        return;
+    }
+
+    log(&format!(
+        "migrate passwords of {} users and {} games",
+        users.len(),
+        games.len()
+    ));
+    for mut user in users {
+        user.pwd = hash(&user.pwd);
+        storage.put_user(user);
+    }
+    for mut game in games {
+        game.black_user.pwd.clear();
+        game.white_user.pwd.clear();
+        storage.add_game(game);
+    }
+}
@@ -0,0 +1,74 @@
+//! Rules for new accounts
+
+use super::info;
+
+const ID_LENGTH: std::ops::RangeInclusive<usize> = 3..=20;
+const PASSWORD_LENGTH: std::ops::RangeInclusive<usize> = 8..=128;
+/// names the server uses itself, compared without case
+const RESERVED: [&str; 5] = ["anonymous", "admin", "server", "system", "guest"];
+
+#[derive(thiserror::Error, Debug)]
+pub enum RegisterError {
+    #[error("id {0} is already taken")]
+    DuplicateId(String),
+    #[error("id must be {} to {} characters long", ID_LENGTH.start(), ID_LENGTH.end())]
+    IdLength,
+    #[error("id must start with a letter and use only letters, digits, _ and -")]
+    IdCharacters,
+    #[error("id {0} is reserved")]
+    ReservedId(String),
+    #[error("password is too weak: {0}")]
+    WeakPassword(&'static str),
+}
+
+impl RegisterError {
+    /// stable name of the error for clients
+    pub fn code(&self) -> &'static str {
+        match self {
+            RegisterError::DuplicateId(_) => "duplicate_id",
+            RegisterError::IdLength => "id_length",
+            RegisterError::IdCharacters => "id_characters",
+            RegisterError::ReservedId(_) => "reserved_id",
+            RegisterError::WeakPassword(_) => "weak_password",
+        }
+    }
+}
+
+impl From<RegisterError> for info::ErrorInfo {
+    fn from(value: RegisterError) -> Self {
+        info::ErrorInfo {
+            code: value.code().to_string(),
+            message: value.to_string(),
+        }
+    }
+}
+
+/// check the id and password of a new account, not whether the id is taken
+pub fn check(info: &info::RegisterInfo) -> Result<(), RegisterError> {
+    let id = info.id.as_str();
+    if !ID_LENGTH.contains(&id.chars().count()) {
+        return Err(RegisterError::IdLength);
+    }
+    let valid = id.chars().next().is_some_and(|c| c.is_ascii_alphabetic())
+        && id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+    if !valid {
+        return Err(RegisterError::IdCharacters);
+    }
+    if RESERVED.iter().any(|name| name.eq_ignore_ascii_case(id)) {
+        return Err(RegisterError::ReservedId(id.to_string()));
+    }
+
+    let pwd = info.pwd.as_str();
+    if !PASSWORD_LENGTH.contains(&pwd.chars().count()) {
+        return Err(RegisterError::WeakPassword("use 8 to 128 characters"));
+    }
+    if !pwd.chars().any(|c| c.is_alphabetic()) || !pwd.chars().any(|c| c.is_numeric()) {
+        return Err(RegisterError::WeakPassword("use both letters and digits"));
+    }
+    if pwd.to_lowercase().contains(&id.to_lowercase()) {
+        return Err(RegisterError::WeakPassword("do not use the id in it"));
+    }
+    Ok(())
+}
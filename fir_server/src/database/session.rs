@@ -0,0 +1,51 @@
+//! Login sessions
+//!
+//! A session key is 32 random bytes written in hex. Keys are kept by the
+//! storage together with the user they belong to and when they expire.
+
+use super::info;
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Session {
+    pub key: String,
+    /// id of the user who logged in
+    pub user: String,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+}
+
+impl Session {
+    /// new session for `user` that lasts `ttl`
+    pub fn new(user: &str, ttl: Duration) -> Self {
+        let mut bytes = [0u8; 32];
+        OsRng.fill_bytes(&mut bytes);
+        let now = Utc::now();
+        Session {
+            key: bytes.iter().map(|b| format!("{b:02x}")).collect(),
+            user: user.to_string(),
+            created_at: now,
+            expires_at: now + ttl,
+        }
+    }
+
+    pub fn is_expired(&self, now: DateTime<Utc>) -> bool {
+        self.expires_at <= now
+    }
+
+    /// short name of the session that does not give it away
+    pub fn id(&self) -> &str {
+        self.key.get(..8).unwrap_or(&self.key)
+    }
+}
+
+impl From<&Session> for info::UserKeyInfo {
+    fn from(value: &Session) -> Self {
+        info::UserKeyInfo {
+            key: value.key.clone(),
+            expiretime: value.expires_at.to_rfc3339(),
+        }
+    }
+}
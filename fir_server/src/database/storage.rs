@@ -0,0 +1,449 @@
+//! Storage backends
+//!
+//! [Database](super::Database) keeps its data in a [Storage]. The memory
+//! backend is lost on exit, the journal backend writes every change to the
+//! data directory.
+
+use super::data::{GameData, UserData};
+use super::journal::JournalStorage;
+use super::session::Session;
+use crate::config::{Config, StorageKind};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// where users, sessions, games and ratings are kept
+pub trait Storage: Send + Sync {
+    /// add a new user, false if the id is taken
+    fn add_user(&mut self, user: UserData) -> bool;
+    /// store a user under its id, replacing the stored one
+    ///
+    /// for changes to existing users, new accounts go through
+    /// [Storage::add_user].
+    fn put_user(&mut self, user: UserData);
+    fn user(&self, id: &str) -> Option<&UserData>;
+    fn users(&self) -> &[UserData];
+
+    fn add_session(&mut self, session: Session);
+    fn session(&self, key: &str) -> Option<&Session>;
+    /// returns false when there is no such session
+    fn remove_session(&mut self, key: &str) -> bool;
+    /// sessions of the user with `id`, expired ones included
+    fn user_sessions(&self, id: &str) -> Vec<&Session>;
+    /// drop the sessions expired at `now` and return how many there were
+    fn expire_sessions(&mut self, now: DateTime<Utc>) -> usize;
+
+    /// store a finished game and return its id
+    ///
+    /// a game with the id of a stored game replaces it.
+    fn add_game(&mut self, game: GameData) -> u64;
+    /// id for a game still to be stored, no other game gets it
+    fn reserve_game_id(&mut self) -> u64;
+    fn game(&self, id: u64) -> Option<&GameData>;
+    fn games(&self) -> &[GameData];
+    /// games played by the user with `id`
+    fn user_games(&self, id: &str) -> Vec<&GameData>;
+}
+
+/// storage selected by `config`
+pub fn open(config: &Config) -> std::io::Result<Box<dyn Storage>> {
+    Ok(match config.storage {
+        StorageKind::Memory => Box::new(MemoryStorage::default()),
+        StorageKind::Journal => Box::new(JournalStorage::open(&config.data_dir)?),
+    })
+}
+
+/// everything in memory, also the snapshot format of the journal
+///
+/// users and games live in plain lists, the maps index into them and are
+/// rebuilt with [MemoryStorage::reindex] after loading.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct MemoryStorage {
+    users: Vec<UserData>,
+    games: Vec<GameData>,
+    /// session key to session
+    #[serde(default)]
+    sessions: HashMap<String, Session>,
+    #[serde(skip)]
+    user_ids: HashMap<String, usize>,
+    /// user id to the keys of the user's sessions
+    #[serde(skip)]
+    session_keys: HashMap<String, Vec<String>>,
+    #[serde(skip)]
+    game_ids: HashMap<u64, usize>,
+    /// user id to the games the user played, oldest first
+    #[serde(skip)]
+    player_games: HashMap<String, Vec<usize>>,
+    /// largest game id given so far
+    #[serde(skip)]
+    last_game_id: u64,
+}
+
+impl MemoryStorage {
+    /// rebuild the maps from the lists
+    pub fn reindex(&mut self) {
+        self.user_ids.clear();
+        for (index, user) in self.users.iter().enumerate() {
+            self.user_ids.insert(user.id.clone(), index);
+        }
+        self.session_keys.clear();
+        for session in self.sessions.values() {
+            self.session_keys
+                .entry(session.user.clone())
+                .or_default()
+                .push(session.key.clone());
+        }
+        self.game_ids.clear();
+        self.player_games.clear();
+        self.last_game_id = self.games.iter().map(|g| g.id).max().unwrap_or(0);
+        for index in 0..self.games.len() {
+            self.index_game(index);
+        }
+    }
+
+    /// give the game an id if it has none yet and add it to the maps
+    fn index_game(&mut self, index: usize) {
+        if self.games[index].id == 0 {
+            self.last_game_id += 1;
+            self.games[index].id = self.last_game_id;
+        }
+        self.last_game_id = self.last_game_id.max(self.games[index].id);
+        let game = &self.games[index];
+        self.game_ids.insert(game.id, index);
+        let mut players = vec![game.black_user.id.clone()];
+        if game.white_user.id != game.black_user.id {
+            players.push(game.white_user.id.clone());
+        }
+        for id in players {
+            self.player_games.entry(id).or_default().push(index);
+        }
+    }
+}
+
+impl Storage for MemoryStorage {
+    fn add_user(&mut self, user: UserData) -> bool {
+        if self.user_ids.contains_key(&user.id) {
+            return false;
+        }
+        self.put_user(user);
+        true
+    }
+
+    fn put_user(&mut self, user: UserData) {
+        match self.user_ids.get(&user.id) {
+            Some(&index) => self.users[index] = user,
+            None => {
+                self.user_ids.insert(user.id.clone(), self.users.len());
+                self.users.push(user);
+            }
+        }
+    }
+
+    fn user(&self, id: &str) -> Option<&UserData> {
+        self.user_ids.get(id).map(|&index| &self.users[index])
+    }
+
+    fn users(&self) -> &[UserData] {
+        &self.users
+    }
+
+    fn add_session(&mut self, session: Session) {
+        self.session_keys
+            .entry(session.user.clone())
+            .or_default()
+            .push(session.key.clone());
+        self.sessions.insert(session.key.clone(), session);
+    }
+
+    fn session(&self, key: &str) -> Option<&Session> {
+        self.sessions.get(key)
+    }
+
+    fn remove_session(&mut self, key: &str) -> bool {
+        let Some(session) = self.sessions.remove(key) else {
+            return false;
+        };
+        if let Some(keys) = self.session_keys.get_mut(&session.user) {
+            keys.retain(|k| k != key);
+        }
+        true
+    }
+
+    fn user_sessions(&self, id: &str) -> Vec<&Session> {
+        self.session_keys
+            .get(id)
+            .map(|keys| keys.iter().filter_map(|k| self.sessions.get(k)).collect())
+            .unwrap_or_default()
+    }
+
+    fn expire_sessions(&mut self, now: DateTime<Utc>) -> usize {
+        let expired: Vec<String> = self
+            .sessions
+            .values()
+            .filter(|session| session.is_expired(now))
+            .map(|session| session.key.clone())
+            .collect();
+        for key in expired.iter() {
+            self.remove_session(key);
+        }
+        expired.len()
+    }
+
+    fn add_game(&mut self, game: GameData) -> u64 {
+        if let Some(&index) = self.game_ids.get(&game.id) {
+            self.games[index] = game;
+            return self.games[index].id;
+        }
+        self.games.push(game);
+        let index = self.games.len() - 1;
+        self.index_game(index);
+        self.games[index].id
+    }
+
+    fn reserve_game_id(&mut self) -> u64 {
+        self.last_game_id += 1;
+        self.last_game_id
+    }
+
+    fn game(&self, id: u64) -> Option<&GameData> {
+        self.game_ids.get(&id).map(|&index| &self.games[index])
+    }
+
+    fn games(&self) -> &[GameData] {
+        &self.games
+    }
+
+    fn user_games(&self, id: &str) -> Vec<&GameData> {
+        self.player_games
+            .get(id)
+            .map(|games| games.iter().map(|&index| &self.games[index]).collect())
+            .unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::data::GameResult;
+    use super::*;
+    use std::hint::black_box;
+    use std::time::{Duration, Instant};
+
+    fn user(id: &str) -> UserData {
+        UserData::new(String::from(id), String::new(), 600)
+    }
+
+    /// alice, bob and carol with a session each and three games
+    fn storage() -> MemoryStorage {
+        let mut storage = MemoryStorage::default();
+        for id in ["alice", "bob", "carol"] {
+            assert!(storage.add_user(user(id)));
+            storage.add_session(Session::new(id, chrono::Duration::hours(1)));
+        }
+        storage.add_game(GameData::new(user("alice"), user("bob")));
+        storage.add_game(GameData::new(user("bob"), user("carol")));
+        storage.add_game(GameData::new(user("alice"), user("alice")));
+        storage
+    }
+
+    fn game_ids(games: Vec<&GameData>) -> Vec<u64> {
+        games.iter().map(|g| g.id).collect()
+    }
+
+    #[test]
+    fn add_user_rejects_taken_id() {
+        let mut storage = storage();
+        let mut alice = user("alice");
+        alice.rating = 900;
+        assert!(!storage.add_user(alice.clone()));
+        assert_eq!(storage.users().len(), 3);
+        assert_eq!(storage.user("alice").unwrap().rating, 600);
+
+        storage.put_user(alice);
+        assert_eq!(storage.users().len(), 3);
+        assert_eq!(storage.user("alice").unwrap().rating, 900);
+    }
+
+    #[test]
+    fn lookups_follow_the_lists() {
+        let storage = storage();
+        assert_eq!(storage.user("carol").unwrap().id, "carol");
+        assert!(storage.user("dave").is_none());
+        assert_eq!(game_ids(storage.user_games("alice")), vec![1, 3]);
+        assert_eq!(game_ids(storage.user_games("bob")), vec![1, 2]);
+        assert!(storage.user_games("dave").is_empty());
+        assert_eq!(storage.game(2).unwrap().white_user.id, "carol");
+        assert!(storage.game(4).is_none());
+
+        let key = storage.user_sessions("bob")[0].key.clone();
+        assert_eq!(storage.session(&key).unwrap().user, "bob");
+    }
+
+    #[test]
+    fn add_game_keeps_ids() {
+        let mut storage = storage();
+        let mut game = storage.game(2).unwrap().clone();
+        game.result = GameResult::Draw;
+        assert_eq!(storage.add_game(game), 2);
+        assert_eq!(storage.games().len(), 3);
+        assert!(matches!(storage.game(2).unwrap().result, GameResult::Draw));
+
+        let mut game = GameData::new(user("carol"), user("alice"));
+        game.id = 10;
+        assert_eq!(storage.add_game(game), 10);
+        assert_eq!(
+            storage.add_game(GameData::new(user("bob"), user("alice"))),
+            11
+        );
+    }
+
+    #[test]
+    fn reserved_game_ids_are_kept() {
+        let mut storage = storage();
+        let id = storage.reserve_game_id();
+        assert_eq!(id, 4);
+        assert_eq!(
+            storage.add_game(GameData::new(user("bob"), user("alice"))),
+            5
+        );
+
+        let mut game = GameData::new(user("carol"), user("alice"));
+        game.id = id;
+        assert_eq!(storage.add_game(game), id);
+        assert_eq!(storage.game(id).unwrap().black_user.id, "carol");
+        assert_eq!(storage.games().len(), 5);
+    }
+
+    #[test]
+    fn remove_and_expire_sessions() {
+        let mut storage = storage();
+        let key = storage.user_sessions("alice")[0].key.clone();
+        assert!(storage.remove_session(&key));
+        assert!(!storage.remove_session(&key));
+        assert!(storage.session(&key).is_none());
+        assert!(storage.user_sessions("alice").is_empty());
+
+        let later = Utc::now() + chrono::Duration::hours(2);
+        assert_eq!(storage.expire_sessions(later), 2);
+        assert!(storage.user_sessions("bob").is_empty());
+    }
+
+    #[test]
+    fn reindex_after_loading() {
+        let storage = storage();
+        let json = serde_json::to_string(&storage).unwrap();
+        let mut loaded: MemoryStorage = serde_json::from_str(&json).unwrap();
+        assert!(loaded.user("alice").is_none());
+
+        loaded.reindex();
+        assert_eq!(loaded.user("bob").unwrap().id, "bob");
+        assert_eq!(game_ids(loaded.user_games("alice")), vec![1, 3]);
+        assert_eq!(game_ids(loaded.user_games("carol")), vec![2]);
+        assert_eq!(loaded.user_sessions("carol").len(), 1);
+        assert!(!loaded.add_user(user("carol")));
+        // new games go on after the largest loaded id
+        assert_eq!(
+            loaded.add_game(GameData::new(user("bob"), user("alice"))),
+            4
+        );
+    }
+
+    #[test]
+    fn reindex_numbers_games_without_id() {
+        let mut storage = MemoryStorage {
+            games: vec![
+                GameData::new(user("alice"), user("bob")),
+                GameData {
+                    id: 5,
+                    ..GameData::new(user("bob"), user("alice"))
+                },
+            ],
+            ..MemoryStorage::default()
+        };
+        storage.reindex();
+        assert_eq!(storage.games()[0].id, 6);
+        assert_eq!(game_ids(storage.user_games("bob")), vec![6, 5]);
+    }
+
+    /// time indexed lookups against plain scans on generated data
+    ///
+    /// run with `cargo test -p fir_server index_lookups -- --ignored --nocapture`
+    #[test]
+    #[ignore]
+    fn index_lookups_against_scans() {
+        const LOOKUPS: usize = 1000;
+        let (users, games) = (20000, 50000);
+
+        let mut storage = MemoryStorage::default();
+        for i in 0..users {
+            storage.add_user(UserData::new(format!("user{i}"), String::new(), 600));
+            storage.add_session(Session::new(
+                &format!("user{i}"),
+                chrono::Duration::hours(1),
+            ));
+        }
+        for i in 0..games {
+            let black = storage.users[i % users].clone();
+            let white = storage.users[(i * 7 + 1) % users].clone();
+            storage.add_game(GameData::new(black, white));
+        }
+        let ids: Vec<String> = (0..LOOKUPS)
+            .map(|i| format!("user{}", i * 7919 % users))
+            .collect();
+        let keys: Vec<String> = ids
+            .iter()
+            .map(|id| storage.user_sessions(id)[0].key.clone())
+            .collect();
+
+        let time = |f: &dyn Fn(&str) -> usize, inputs: &[String]| {
+            let start = Instant::now();
+            let found: usize = inputs.iter().map(|input| black_box(f(input))).sum();
+            (start.elapsed(), found)
+        };
+        let compare = |name: &str, scan: (Duration, usize), index: (Duration, usize)| {
+            format!(
+                "{name}: scan {:?}, index {:?}, {:.0}x faster, {} found",
+                scan.0,
+                index.0,
+                scan.0.as_secs_f64() / index.0.as_secs_f64().max(1e-9),
+                index.1,
+            )
+        };
+
+        let lines = vec![
+            format!("{users} users, {games} games, {LOOKUPS} lookups each"),
+            compare(
+                "user by id",
+                time(
+                    &|id| storage.users.iter().filter(|u| u.id == id).count(),
+                    &ids,
+                ),
+                time(&|id| storage.user(id).iter().count(), &ids),
+            ),
+            compare(
+                "session by key",
+                time(
+                    &|key| storage.sessions.values().filter(|s| s.key == key).count(),
+                    &keys,
+                ),
+                time(&|key| storage.session(key).iter().count(), &keys),
+            ),
+            compare(
+                "games of user",
+                time(
+                    &|id| {
+                        storage
+                            .games
+                            .iter()
+                            .filter(|g| g.black_user.id == id || g.white_user.id == id)
+                            .count()
+                    },
+                    &ids,
+                ),
+                time(&|id| storage.user_games(id).len(), &ids),
+            ),
+        ];
+        for line in lines {
+            println!("{line}");
+        }
+    }
+}
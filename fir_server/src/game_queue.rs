@@ -1,36 +1,120 @@
-use crate::database::{data, info};
-use crate::socket::Socket;
-use crate::{database::data::*, match_queue::UserRegisterData, prelude::*};
-use fir_game;
+use crate::clock::Clock;
+use crate::database::{data, info, UpdateQuery};
+use crate::{
+    database::data::*,
+    match_queue::{MatchSettings, SocketChannel, UserRegisterData},
+    prelude::*,
+};
+use chrono::{DateTime, Utc};
+use fir_game::RuleSet;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::broadcast::{self, error::RecvError};
 use tokio::sync::mpsc::{channel, Receiver, Sender};
+use tokio::sync::{oneshot, RwLock};
+use tokio::time::Instant;
+
+/// width of the board games are played on
+const BOARD_SIZE: usize = 8;
 
 pub struct GameInitData {
     player1: UserRegisterData,
     player2: UserRegisterData,
-    time: TimeControl,
+    settings: MatchSettings,
 }
 
 impl GameInitData {
-    pub fn new(player1: UserRegisterData, player2: UserRegisterData, time: TimeControl) -> Self {
+    pub fn new(
+        player1: UserRegisterData,
+        player2: UserRegisterData,
+        settings: MatchSettings,
+    ) -> Self {
         GameInitData {
             player1,
             player2,
-            time,
+            settings,
         }
     }
 }
 
+/// what reaches a game room from outside
+#[derive(Debug)]
+pub enum RoomEvent {
+    /// message from a player's `generation`th connection
+    Command(Side, u32, String),
+    /// that connection closed
+    Disconnect(Side, u32),
+    /// new connection of the user with the id
+    Reconnect(String, SocketChannel),
+    /// connection of someone who wants to watch
+    Spectate(SocketChannel),
+    /// the spectator with the number closed the connection
+    SpectatorLeft(u32),
+}
+
+/// game in progress, as the rest of the server sees it
+#[derive(Debug, Clone)]
+pub struct LiveGame {
+    pub id: u64,
+    /// black, white
+    pub players: [String; 2],
+    pub rule: RuleSet,
+    pub time: TimeControl,
+    pub started_at: DateTime<Utc>,
+    events: Sender<RoomEvent>,
+}
+
+impl LiveGame {
+    /// hand a new connection of a player to the room
+    pub async fn reconnect(&self, id: String, channel: SocketChannel) {
+        // the room may have ended meanwhile
+        let _ = self.events.send(RoomEvent::Reconnect(id, channel)).await;
+    }
+
+    /// let the owner of the connection watch the game
+    ///
+    /// false if the game has ended meanwhile.
+    pub async fn spectate(&self, channel: SocketChannel) -> bool {
+        self.events.send(RoomEvent::Spectate(channel)).await.is_ok()
+    }
+}
+
+impl From<&LiveGame> for info::LiveGameInfo {
+    fn from(value: &LiveGame) -> Self {
+        info::LiveGameInfo {
+            id: value.id,
+            black: value.players[0].clone(),
+            white: value.players[1].clone(),
+            rule: value.rule.to_string(),
+            timecontrol: value.time.to_string(),
+            starttime: value.started_at.to_rfc3339(),
+        }
+    }
+}
+
+/// games in progress by the id they are stored under when they end
+pub type LiveGames = Arc<RwLock<HashMap<u64, LiveGame>>>;
+
 pub struct GameQueue {
     sender: Sender<GameInitData>,
     receiver: Receiver<GameInitData>,
+    live: LiveGames,
+    /// whether rated games allow takebacks
+    rated_takebacks: bool,
+    /// how long a game waits for a player who lost the connection
+    reconnect_grace: Duration,
 }
 
 impl GameQueue {
-    pub fn new() -> Self {
+    pub fn new(config: &crate::config::Config) -> Self {
         let (tx, rx) = tokio::sync::mpsc::channel(100);
         GameQueue {
             sender: tx,
             receiver: rx,
+            live: LiveGames::default(),
+            rated_takebacks: config.rated_takebacks,
+            reconnect_grace: config.reconnect_grace,
         }
     }
 
@@ -38,187 +122,757 @@ impl GameQueue {
         self.sender.clone()
     }
 
+    pub fn get_live_games(&self) -> LiveGames {
+        Arc::clone(&self.live)
+    }
+
     pub async fn run(mut self, sender: Sender<crate::database::UpdateQuery>) {
         loop {
             let recv = self.receiver.recv().await.unwrap();
 
-            let gameroom = GameRoom::from_data(recv);
-            tokio::spawn(gameroom.run(sender.clone()));
+            // spectators and the stored game share the id
+            let (reply, id) = oneshot::channel();
+            sender
+                .send(UpdateQuery::ReserveGameId(reply))
+                .await
+                .unwrap();
+            let id = id.await.unwrap();
+            let gameroom =
+                GameRoom::from_data(id, recv, self.rated_takebacks, self.reconnect_grace);
+            self.live.write().await.insert(id, gameroom.live_game());
+
+            let live = Arc::clone(&self.live);
+            let sender = sender.clone();
+            tokio::spawn(async move {
+                gameroom.run(sender).await;
+                live.write().await.remove(&id);
+            });
         }
     }
 }
 
 pub struct GameRoom {
+    /// id of the game in storage
+    id: u64,
     // 0: black, 1: white
     users: [UserRegisterData; 2],
+    time: TimeControl,
+    rule: RuleSet,
+    rated_takebacks: bool,
+    reconnect_grace: Duration,
+    events: Sender<RoomEvent>,
+    receiver: Receiver<RoomEvent>,
     //game: fir_game::FirGame,
 }
 
 impl GameRoom {
-    pub fn new(player1: UserRegisterData, player2: UserRegisterData) -> Self {
+    pub fn from_data(
+        id: u64,
+        data: GameInitData,
+        rated_takebacks: bool,
+        reconnect_grace: Duration,
+    ) -> Self {
+        let (events, receiver) = channel(10);
         GameRoom {
-            users: [player1, player2],
+            id,
+            users: [data.player1, data.player2],
+            time: data.settings.time,
+            rule: data.settings.rule,
+            rated_takebacks,
+            reconnect_grace,
+            events,
+            receiver,
             //game: fir_game::FirGame::new(),
         }
     }
 
-    pub fn from_data(data: GameInitData) -> Self {
-        GameRoom {
-            users: [data.player1, data.player2],
-            //game: fir_game::FirGame::new(),
+    fn live_game(&self) -> LiveGame {
+        LiveGame {
+            id: self.id,
+            players: [self.users[0].data.id.clone(), self.users[1].data.id.clone()],
+            rule: self.rule,
+            time: self.time,
+            started_at: Utc::now(),
+            events: Sender::clone(&self.events),
         }
     }
 
+    /// side of the user with `id`, anonymous players cannot be told apart
+    fn side_of(&self, id: &str) -> Option<Side> {
+        [Side::Black, Side::White].into_iter().find(|side| {
+            let user = &self.users[side.index()].data;
+            !user.is_anonymous() && user.id == id
+        })
+    }
+
     // this function bring its data,
     // so data will be deleted when this function ends
     pub async fn run(mut self, sender: Sender<crate::database::UpdateQuery>) {
         log("Game Start!");
 
-        // make socket handler
-        let socket0 = Socket::new(self.users[0].open_stream.take().unwrap());
-        let socket1 = Socket::new(self.users[1].open_stream.take().unwrap());
+        // sockets were opened by the match queue
+        let (player0_tx, player0_rx) = self.users[0].channel.take().unwrap();
+        let (player1_tx, player1_rx) = self.users[1].channel.take().unwrap();
+        let mut players = [player0_tx, player1_tx];
+
+        // messages of both sockets come to one receiver, tagged with the
+        // side and the connection they came from
+        let mut generation = [0u32; 2];
+        forward(Side::Black, 0, player0_rx, Sender::clone(&self.events));
+        forward(Side::White, 0, player1_rx, Sender::clone(&self.events));
+        // when players who lost the connection forfeit
+        let mut away: [Option<Instant>; 2] = [None, None];
+        let mut spectators = Spectators::default();
 
-        let (player0_tx, mut player0_rx) = socket0.get_channel();
-        let (player1_tx, mut player1_rx) = socket1.get_channel();
+        // make game
+        let mut game = fir_game::FirGame::with_rule(BOARD_SIZE, self.rule);
+        let mut gamedata =
+            data::GameData::new(self.users[0].data.clone(), self.users[1].data.clone());
+        gamedata.id = self.id;
+        gamedata.time = Some(self.time);
+        gamedata.rule = self.rule;
+        gamedata.board_size = BOARD_SIZE;
+        gamedata.rated = !gamedata.black_user.is_anonymous()
+            && !gamedata.white_user.is_anonymous()
+            && gamedata.black_user.id != gamedata.white_user.id;
+        let mut clock = Clock::new(self.time, Instant::now());
+        let mut draw_offers = DrawOffers::default();
+        let takebacks = !gamedata.rated || self.rated_takebacks;
+        // side whose takeback request waits for an answer
+        let mut takeback: Option<Side> = None;
 
-        tokio::spawn(socket0.run());
-        tokio::spawn(socket1.run());
+        // send color
+        let clocks = clock.clocks(Instant::now());
+        let command = data::GameResponse::Start(Side::Black, self.users[1].data.id.clone(), clocks);
+        send_response(&players[0], command);
+        let command = data::GameResponse::Start(Side::White, self.users[0].data.id.clone(), clocks);
+        send_response(&players[1], command);
 
-        // make two receiver to one receiver with mpsc
-        let (tx, mut rx) = channel(10);
+        // game command handler
+        loop {
+            let forfeit_at = away.iter().flatten().min().copied();
+            let event = tokio::select! {
+                event = self.receiver.recv() => event,
+                _ = tokio::time::sleep_until(clock.deadline()) => {
+                    time_out(game.order().into(), gamedata, &sender, &players, &spectators).await;
+                    break;
+                }
+                _ = tokio::time::sleep_until(forfeit_at.unwrap_or_else(Instant::now)),
+                    if forfeit_at.is_some() =>
+                {
+                    let side = if away[0] == forfeit_at { Side::Black } else { Side::White };
+                    log(&format!("{side:?} did not come back"));
+                    // nobody wins if both players are gone
+                    let result = if away[side.opponent().index()].is_some() {
+                        GameResult::Abort
+                    } else {
+                        GameResult::Win(side.opponent())
+                    };
+                    gamedata.finish(result, Termination::Disconnect);
+                    end_game(gamedata, &sender, &players, &spectators).await;
+                    break;
+                }
+            };
+            // the room holds a sender itself, so the channel stays open
+            let Some(event) = event else {
+                break;
+            };
 
-        let tx1 = Sender::clone(&tx);
-        tokio::spawn(async move {
-            loop {
-                if let Ok(message) = player0_rx.recv().await {
-                    if let Stopper::Go(message) = message {
-                        tx1.send(message).await.unwrap();
+            let (side, message) = match event {
+                RoomEvent::Command(side, connection, message)
+                    if connection == generation[side.index()] =>
+                {
+                    (side, message)
+                }
+                RoomEvent::Disconnect(side, connection)
+                    if connection == generation[side.index()] =>
+                {
+                    // anonymous players have no key to come back with
+                    let grace = if self.users[side.index()].data.is_anonymous() {
+                        Duration::ZERO
                     } else {
+                        self.reconnect_grace
+                    };
+                    log(&format!("{side:?} disconnected"));
+                    away[side.index()] = Some(Instant::now() + grace);
+                    send_response(
+                        &players[side.opponent().index()],
+                        data::GameResponse::OpponentDisconnected,
+                    );
+                    continue;
+                }
+                RoomEvent::Reconnect(id, (tx, rx)) => {
+                    let Some(side) = self.side_of(&id) else {
+                        continue;
+                    };
+                    let i = side.index();
+                    log(&format!("{side:?} reconnected"));
+                    // a connection still open is replaced
+                    let _ = players[i].send(Stopper::Stop);
+                    players[i] = tx;
+                    generation[i] += 1;
+                    forward(side, generation[i], rx, Sender::clone(&self.events));
+                    away[i] = None;
+
+                    let state = game_state(&game, &gamedata, &clock);
+                    send_response(&players[i], data::GameResponse::Resync(side, state));
+                    send_response(
+                        &players[side.opponent().index()],
+                        data::GameResponse::OpponentReconnected,
+                    );
+                    if !spectators.sockets.is_empty() {
+                        let response = data::GameResponse::Spectators(spectators.sockets.len());
+                        send_response(&players[i], response);
+                    }
+                    continue;
+                }
+                RoomEvent::Spectate((tx, rx)) => {
+                    let state = game_state(&game, &gamedata, &clock);
+                    send_response(&tx, data::GameResponse::Snapshot(state));
+                    let id = spectators.add(tx.clone());
+                    spectate(id, tx, rx, Sender::clone(&self.events));
+                    log(&format!("spectator {id} joined"));
+                    let response = data::GameResponse::Spectators(spectators.sockets.len());
+                    for tx in &players {
+                        send_response(tx, response.clone());
+                    }
+                    continue;
+                }
+                RoomEvent::SpectatorLeft(id) => {
+                    if spectators.sockets.remove(&id).is_some() {
+                        log(&format!("spectator {id} left"));
+                        let response = data::GameResponse::Spectators(spectators.sockets.len());
+                        for tx in &players {
+                            send_response(tx, response.clone());
+                        }
+                    }
+                    continue;
+                }
+                // from a connection that was replaced
+                _ => continue,
+            };
+
+            log(&format!("game receive message: {message:?}"));
+            let command = serde_json::from_str::<info::GameCommandInfo>(&message)
+                .ok()
+                .and_then(|command| data::GameCommand::try_from(command).ok());
+            let Some(command) = command else {
+                send_response(
+                    &players[side.index()],
+                    data::GameResponse::Message(String::from("invalid command")),
+                );
+                continue;
+            };
+            // the socket tells who sent it, not the message
+            let opponent = &players[side.opponent().index()];
+
+            match command.command_type {
+                data::CommandType::Message => {
+                    send_response(opponent, data::GameResponse::Message(command.message));
+                }
+                data::CommandType::Play => {
+                    if Side::from(game.order()) != side {
+                        let response = data::GameResponse::Message(String::from("not your turn"));
+                        send_response(&players[side.index()], response);
+                        continue;
+                    }
+                    // the move may arrive together with the deadline
+                    let now = Instant::now();
+                    let pressed = clock.clone();
+                    let Some(left) = clock.press(now) else {
+                        time_out(side, gamedata, &sender, &players, &spectators).await;
+                        break;
+                    };
+                    let played = game.play(command.notation.x, command.notation.y, side.into());
+                    if let Err(e) = played {
+                        clock = pressed;
+                        // renju forbids some moves to black
+                        let response = data::GameResponse::Message(e.to_string());
+                        send_response(&players[side.index()], response);
+                        continue;
+                    }
+                    gamedata.play(command.notation, Some(left.as_millis() as u64));
+                    draw_offers.expire();
+                    takeback = None;
+                    log(&game.to_unicode());
+
+                    let clocks = clock.clocks(now);
+                    let response = data::GameResponse::OpponentPlay(command.notation, clocks);
+                    send_response(opponent, response);
+                    send_response(&players[side.index()], data::GameResponse::Clock(clocks));
+                    spectators.send(data::GameResponse::Move(command.notation, clocks));
+
+                    // check game end
+                    let (result, side) = game.is_end();
+                    if result {
+                        gamedata.finish(GameResult::Win(side.into()), Termination::Five);
+                        end_game(gamedata, &sender, &players, &spectators).await;
                         break;
                     }
                 }
-            }
-        });
-
-        let tx2 = Sender::clone(&tx);
-        tokio::spawn(async move {
-            loop {
-                if let Ok(message) = player1_rx.recv().await {
-                    if let Stopper::Go(message) = message {
-                        tx2.send(message).await.unwrap();
-                    } else {
+                data::CommandType::Resign => {
+                    send_response(opponent, data::GameResponse::OpponentResign);
+                    gamedata.finish(GameResult::Resign(side), Termination::Resign);
+                    end_game(gamedata, &sender, &players, &spectators).await;
+                    break;
+                }
+                data::CommandType::OfferDraw => {
+                    // offering against an open offer agrees to it
+                    if draw_offers.answer(side) {
+                        send_response(opponent, data::GameResponse::OpponentAcceptDraw);
+                        gamedata.finish(GameResult::Draw, Termination::DrawAgreement);
+                        end_game(gamedata, &sender, &players, &spectators).await;
                         break;
                     }
+                    match draw_offers.offer(side) {
+                        Ok(()) => send_response(opponent, data::GameResponse::OpponentOfferDraw),
+                        Err(reason) => send_response(
+                            &players[side.index()],
+                            data::GameResponse::Message(String::from(reason)),
+                        ),
+                    }
                 }
-            }
-        });
+                data::CommandType::AcceptDraw => {
+                    if !draw_offers.answer(side) {
+                        let response = data::GameResponse::Message(String::from("no draw offer"));
+                        send_response(&players[side.index()], response);
+                        continue;
+                    }
+                    send_response(opponent, data::GameResponse::OpponentAcceptDraw);
+                    gamedata.finish(GameResult::Draw, Termination::DrawAgreement);
+                    end_game(gamedata, &sender, &players, &spectators).await;
+                    break;
+                }
+                data::CommandType::DeclineDraw => {
+                    if draw_offers.answer(side) {
+                        send_response(opponent, data::GameResponse::OpponentDeclineDraw);
+                    }
+                }
+                data::CommandType::RequestTakeback => {
+                    let refusal = if !takebacks {
+                        Some("no takebacks in rated games")
+                    } else if takeback_count(&game, side) == 0 {
+                        Some("no move to take back")
+                    } else if takeback == Some(side) {
+                        Some("your takeback request is still open")
+                    } else {
+                        None
+                    };
+                    if let Some(reason) = refusal {
+                        let response = data::GameResponse::Message(String::from(reason));
+                        send_response(&players[side.index()], response);
+                        continue;
+                    }
+                    takeback = Some(side);
+                    send_response(opponent, data::GameResponse::OpponentRequestTakeback);
+                }
+                data::CommandType::AcceptTakeback => {
+                    let Some(requester) = takeback.filter(|requester| *requester != side) else {
+                        let response =
+                            data::GameResponse::Message(String::from("no takeback request"));
+                        send_response(&players[side.index()], response);
+                        continue;
+                    };
+                    takeback = None;
+                    draw_offers.expire();
 
-        // send color
-        let command = data::GameResponse::Start(Side::Black, self.users[1].data.id.clone());
-        let command: info::GameResponseInfo = command.into();
-        let command = serde_json::to_string(&command).unwrap();
-        player0_tx.send(Stopper::Go(command)).unwrap();
+                    let count = takeback_count(&game, requester);
+                    let moves = &game.moves()[..game.moves().len() - count];
+                    game = fir_game::FirGame::from_moves(BOARD_SIZE, self.rule, moves).unwrap();
+                    gamedata.take_back(count);
+                    let now = Instant::now();
+                    clock.set_turn(requester, now);
+                    log(&format!("{requester:?} took back {count} moves"));
 
-        let command = data::GameResponse::Start(Side::White, self.users[0].data.id.clone());
-        let command: info::GameResponseInfo = command.into();
-        let command = serde_json::to_string(&command).unwrap();
-        player1_tx.send(Stopper::Go(command)).unwrap();
+                    let response =
+                        data::GameResponse::Takeback(gamedata.notations.clone(), clock.clocks(now));
+                    for tx in players.iter().chain(spectators.sockets.values()) {
+                        send_response(tx, response.clone());
+                    }
+                }
+                data::CommandType::DeclineTakeback => {
+                    if takeback == Some(side.opponent()) {
+                        takeback = None;
+                        send_response(opponent, data::GameResponse::OpponentDeclineTakeback);
+                    }
+                }
+                data::CommandType::Cancel => {
+                    // leaving the queue is too late, resigning ends the game
+                    let response =
+                        data::GameResponse::Message(String::from("the game has already started"));
+                    send_response(&players[side.index()], response);
+                }
+            }
+        }
 
-        // make game
-        let mut game = fir_game::FirGame::new();
-        let mut gamedata =
-            data::GameData::new(self.users[0].data.clone(), self.users[1].data.clone());
+        log("Game End!");
+    }
+}
 
-        // game command handler
-        tokio::spawn(async move {
-            loop {
-                if let Some(message) = rx.recv().await {
-                    log(&format!("game receive message: {message:?}"));
-                    let command: info::GameCommandInfo = serde_json::from_str(&message).unwrap();
-                    let command: data::GameCommand = command.into();
-
-                    match command.command_type {
-                        data::CommandType::Message => {
-                            let response = data::GameResponse::Message(command.message);
-                            let response: info::GameResponseInfo = response.into();
-                            let response = serde_json::to_string(&response).unwrap();
-                            if let Side::Black = command.side {
-                                player1_tx.send(Stopper::Go(response)).unwrap();
-                            } else {
-                                player0_tx.send(Stopper::Go(response)).unwrap();
-                            }
-                        }
-                        data::CommandType::Play => {
-                            gamedata.notations.push(command.notation);
-                            game.play(command.notation.x, command.notation.y, command.side.into())
-                                .unwrap();
-                            log(&game.board_state());
-                            let response = data::GameResponse::OpponentPlay(command.notation);
-                            let response: info::GameResponseInfo = response.into();
-                            let response = serde_json::to_string(&response).unwrap();
-                            if let Side::Black = command.side {
-                                player1_tx.send(Stopper::Go(response)).unwrap();
-                            } else {
-                                player0_tx.send(Stopper::Go(response)).unwrap();
-                            }
-
-                            // check game end
-                            let (result, side) = game.is_end();
-                            if result {
-                                let response = data::GameResponse::GameEnd;
-                                let response: info::GameResponseInfo = response.into();
-                                let response = serde_json::to_string(&response).unwrap();
-                                player1_tx.send(Stopper::Go(response.clone())).unwrap();
-                                player0_tx.send(Stopper::Go(response)).unwrap();
-
-                                // stop async functions
-                                player1_tx.send(Stopper::Stop).unwrap();
-                                player0_tx.send(Stopper::Stop).unwrap();
-
-                                gamedata.result = GameResult::Win(side.into());
-                                sender
-                                    .send(crate::database::UpdateQuery::GameData(gamedata))
-                                    .await
-                                    .unwrap();
-                                break;
-                            }
-                        }
-                        data::CommandType::Resign => {
-                            let response = data::GameResponse::OpponentResign;
-                            let response: info::GameResponseInfo = response.into();
-                            let response = serde_json::to_string(&response).unwrap();
-                            if let Side::Black = command.side {
-                                player1_tx.send(Stopper::Go(response)).unwrap();
-                            } else {
-                                player0_tx.send(Stopper::Go(response)).unwrap();
-                            }
-
-                            // send game end response
-                            let response = data::GameResponse::GameEnd;
-                            let response: info::GameResponseInfo = response.into();
-                            let response = serde_json::to_string(&response).unwrap();
-                            player1_tx.send(Stopper::Go(response.clone())).unwrap();
-                            player0_tx.send(Stopper::Go(response)).unwrap();
-
-                            // stop async functions
-                            player1_tx.send(Stopper::Stop).unwrap();
-                            player0_tx.send(Stopper::Stop).unwrap();
-
-                            gamedata.result = GameResult::Resign(command.side);
-                            sender
-                                .send(crate::database::UpdateQuery::GameData(gamedata))
-                                .await
-                                .unwrap();
-                            break;
-                        }
-                        _ => (),
+/// pass the messages of one connection to the room
+fn forward(
+    side: Side,
+    generation: u32,
+    mut receiver: broadcast::Receiver<Stopper<String>>,
+    events: Sender<RoomEvent>,
+) {
+    tokio::spawn(async move {
+        loop {
+            match receiver.recv().await {
+                Ok(Stopper::Go(message)) => {
+                    let event = RoomEvent::Command(side, generation, message);
+                    if events.send(event).await.is_err() {
+                        break;
                     }
                 }
+                Ok(Stopper::Stop) | Err(RecvError::Closed) => {
+                    let _ = events.send(RoomEvent::Disconnect(side, generation)).await;
+                    break;
+                }
+                Err(RecvError::Lagged(_)) => (),
             }
-        })
+        }
+    });
+}
+
+/// answer the messages of a spectator's connection, and tell the room when
+/// it closes
+fn spectate(
+    id: u32,
+    sender: broadcast::Sender<Stopper<String>>,
+    mut receiver: broadcast::Receiver<Stopper<String>>,
+    events: Sender<RoomEvent>,
+) {
+    tokio::spawn(async move {
+        loop {
+            match receiver.recv().await {
+                Ok(Stopper::Go(_)) => {
+                    let response = String::from("spectators cannot send commands");
+                    send_response(&sender, data::GameResponse::Message(response));
+                }
+                Ok(Stopper::Stop) | Err(RecvError::Closed) => {
+                    let _ = events.send(RoomEvent::SpectatorLeft(id)).await;
+                    break;
+                }
+                Err(RecvError::Lagged(_)) => (),
+            }
+        }
+    });
+}
+
+/// people watching a game
+#[derive(Debug, Default)]
+struct Spectators {
+    /// by a number given when they join
+    sockets: HashMap<u32, broadcast::Sender<Stopper<String>>>,
+    next_id: u32,
+}
+
+impl Spectators {
+    /// add a spectator, returning its number
+    fn add(&mut self, sender: broadcast::Sender<Stopper<String>>) -> u32 {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.sockets.insert(id, sender);
+        id
+    }
+
+    fn send(&self, response: data::GameResponse) {
+        for spectator in self.sockets.values() {
+            send_response(spectator, response.clone());
+        }
+    }
+}
+
+/// everything a client needs to show the game
+fn game_state(game: &fir_game::FirGame, gamedata: &data::GameData, clock: &Clock) -> GameState {
+    GameState {
+        black: gamedata.black_user.id.clone(),
+        white: gamedata.white_user.id.clone(),
+        rule: gamedata.rule,
+        time: gamedata.time,
+        turn: game.order().into(),
+        board: game
+            .board_state()
+            .lines()
+            .filter(|row| !row.is_empty())
+            .map(String::from)
+            .collect(),
+        moves: gamedata.notations.clone(),
+        clocks: clock.clocks(Instant::now()),
+    }
+}
+
+/// draw offers made during one game
+#[derive(Debug, Default)]
+struct DrawOffers {
+    /// side whose offer waits for an answer
+    pending: Option<Side>,
+    /// offers made by black, white
+    made: [u32; 2],
+}
+
+impl DrawOffers {
+    /// most offers one player can make in a game
+    const LIMIT: u32 = 3;
+
+    /// record an offer by `side`, or tell why it is refused
+    fn offer(&mut self, side: Side) -> Result<(), &'static str> {
+        let made = match side {
+            Side::Black => &mut self.made[0],
+            Side::White => &mut self.made[1],
+        };
+        if self.pending == Some(side) {
+            Err("your draw offer is still open")
+        } else if *made >= Self::LIMIT {
+            Err("no draw offers left in this game")
+        } else {
+            *made += 1;
+            self.pending = Some(side);
+            Ok(())
+        }
+    }
+
+    /// take the offer `side` is answering, false if there is none
+    fn answer(&mut self, side: Side) -> bool {
+        if self.pending == Some(side.opponent()) {
+            self.pending = None;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// an open offer lapses once a move is played
+    fn expire(&mut self) {
+        self.pending = None;
+    }
+}
+
+/// moves to remove so that `side` plays its last move again, 0 if it has
+/// not moved yet
+fn takeback_count(game: &fir_game::FirGame, side: Side) -> usize {
+    let moves = game.moves().len();
+    let count = if Side::from(game.order()) == side {
+        // the opponent has answered the move
+        2
+    } else {
+        1
+    };
+    if count <= moves {
+        count
+    } else {
+        0
+    }
+}
+
+/// send one response to a player
+///
+/// a player who lost the connection misses it, and gets the whole game on
+/// coming back.
+fn send_response(player: &broadcast::Sender<Stopper<String>>, response: data::GameResponse) {
+    let response: info::GameResponseInfo = response.into();
+    let response = serde_json::to_string(&response).unwrap();
+    let _ = player.send(Stopper::Go(response));
+}
+
+/// `side` ran out of time and loses
+async fn time_out(
+    side: Side,
+    mut gamedata: data::GameData,
+    sender: &Sender<UpdateQuery>,
+    players: &[broadcast::Sender<Stopper<String>>],
+    spectators: &Spectators,
+) {
+    log(&format!("{side:?} ran out of time"));
+    for tx in players.iter().chain(spectators.sockets.values()) {
+        send_response(tx, data::GameResponse::Timeout(side));
+    }
+    gamedata.finish(GameResult::Win(side.opponent()), Termination::Timeout);
+    end_game(gamedata, sender, players, spectators).await;
+}
+
+/// store the finished game, then send the game end with the new ratings
+///
+/// spectators get the result along with the ratings.
+async fn end_game(
+    gamedata: data::GameData,
+    sender: &Sender<UpdateQuery>,
+    players: &[broadcast::Sender<Stopper<String>>],
+    spectators: &Spectators,
+) {
+    let result = gamedata.result.clone();
+    // every game is finished before it ends
+    let termination = gamedata.termination.unwrap();
+    let (reply, change) = oneshot::channel();
+    sender
+        .send(UpdateQuery::FinishedGame(gamedata, reply))
         .await
         .unwrap();
+    let change = change.await.unwrap_or(None);
 
-        log("Game End!");
+    for player in players {
+        send_response(player, data::GameResponse::GameEnd(change));
+        // stop async functions
+        let _ = player.send(Stopper::Stop);
+    }
+    for spectator in spectators.sockets.values() {
+        send_response(
+            spectator,
+            data::GameResponse::Result(result.clone(), termination, change),
+        );
+        let _ = spectator.send(Stopper::Stop);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::time::advance;
+
+    /// the client end of a player's socket
+    struct Client {
+        from_room: broadcast::Receiver<Stopper<String>>,
+        to_room: broadcast::Sender<Stopper<String>>,
+    }
+
+    impl Client {
+        async fn next(&mut self) -> info::GameResponseInfo {
+            match self.from_room.recv().await.unwrap() {
+                Stopper::Go(message) => serde_json::from_str(&message).unwrap(),
+                Stopper::Stop => panic!("socket closed"),
+            }
+        }
+
+        fn send(&self, command: &str, x: u32, y: u32) {
+            let command = info::GameCommandInfo {
+                side: 0,
+                command: String::from(command),
+                notation: info::NotationInfo { color: 0, x, y },
+                message: String::new(),
+            };
+            let command = serde_json::to_string(&command).unwrap();
+            self.to_room.send(Stopper::Go(command)).unwrap();
+        }
+
+        fn play(&self, x: u32, y: u32) {
+            self.send("Play", x, y);
+        }
+    }
+
+    /// give the room a chance to act on the time that passed
+    async fn settle() {
+        for _ in 0..10 {
+            tokio::task::yield_now().await;
+        }
+    }
+
+    fn player(id: &str, settings: MatchSettings) -> (UserRegisterData, Client) {
+        let (to_client, from_room) = broadcast::channel(16);
+        let (to_room, from_client) = broadcast::channel(16);
+        let user = data::UserData::new(String::from(id), String::new(), 600);
+        let user = UserRegisterData::connected(user, settings, (to_client, from_client));
+        (user, Client { from_room, to_room })
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn side_to_move_loses_on_time() {
+        let settings = MatchSettings {
+            time: TimeControl {
+                seconds: 10,
+                fisher: 0,
+            },
+            rule: RuleSet::Freestyle,
+        };
+        let (black_user, mut black) = player("alice", settings);
+        let (white_user, mut white) = player("bob", settings);
+        let init = GameInitData::new(black_user, white_user, settings);
+        let room = GameRoom::from_data(1, init, false, Duration::from_secs(60));
+        let (db, mut queries) = channel(10);
+        let room = tokio::spawn(room.run(db));
+
+        assert_eq!(black.next().await.command, "Start");
+        assert_eq!(white.next().await.command, "Start");
+        advance(Duration::from_secs(3)).await;
+        black.play(3, 3);
+        assert_eq!(white.next().await.command, "OpponentPlay");
+        assert_eq!(black.next().await.command, "Clock");
+
+        // white's ten seconds run from the move
+        advance(Duration::from_secs(9)).await;
+        settle().await;
+        assert!(queries.try_recv().is_err());
+        advance(Duration::from_secs(1)).await;
+        let Some(UpdateQuery::FinishedGame(game, reply)) = queries.recv().await else {
+            panic!("no finished game");
+        };
+        assert_eq!(game.id, 1);
+        assert!(matches!(game.result, GameResult::Win(Side::Black)));
+        assert!(matches!(game.termination, Some(Termination::Timeout)));
+        reply.send(None).unwrap();
+        room.await.unwrap();
+
+        for client in [&mut black, &mut white] {
+            assert_eq!(client.next().await.command, "Timeout");
+            assert_eq!(client.next().await.command, "GameEnd");
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn move_with_the_flag_down_loses_on_time() {
+        let settings = MatchSettings {
+            time: TimeControl {
+                seconds: 10,
+                fisher: 0,
+            },
+            rule: RuleSet::Freestyle,
+        };
+        let (black_user, mut black) = player("alice", settings);
+        let (white_user, mut white) = player("bob", settings);
+        let init = GameInitData::new(black_user, white_user, settings);
+        let room = GameRoom::from_data(1, init, false, Duration::from_secs(60));
+        let (db, mut queries) = channel(10);
+        let room = tokio::spawn(room.run(db));
+
+        assert_eq!(black.next().await.command, "Start");
+        assert_eq!(white.next().await.command, "Start");
+        // the move reaches the room with black's time already gone
+        black.play(3, 3);
+        advance(Duration::from_secs(10)).await;
+        let Some(UpdateQuery::FinishedGame(game, reply)) = queries.recv().await else {
+            panic!("no finished game");
+        };
+        assert!(matches!(game.result, GameResult::Win(Side::White)));
+        assert!(matches!(game.termination, Some(Termination::Timeout)));
+        assert!(game.notations.is_empty());
+        reply.send(None).unwrap();
+        room.await.unwrap();
+
+        for client in [&mut black, &mut white] {
+            assert_eq!(client.next().await.command, "Timeout");
+            assert_eq!(client.next().await.command, "GameEnd");
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn cancel_after_the_start_is_refused() {
+        let settings = MatchSettings::default();
+        let (black_user, mut black) = player("alice", settings);
+        let (white_user, mut white) = player("bob", settings);
+        let init = GameInitData::new(black_user, white_user, settings);
+        let room = GameRoom::from_data(1, init, false, Duration::from_secs(60));
+        let (db, _queries) = channel(10);
+        tokio::spawn(room.run(db));
+
+        assert_eq!(black.next().await.command, "Start");
+        assert_eq!(white.next().await.command, "Start");
+        black.send("Cancel", 0, 0);
+        let response = black.next().await;
+        assert_eq!(response.command, "Message");
+        assert_eq!(response.message, "the game has already started");
+
+        // the game goes on
+        black.play(3, 3);
+        assert_eq!(white.next().await.command, "OpponentPlay");
     }
 }
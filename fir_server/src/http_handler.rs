@@ -1,11 +1,13 @@
-use crate::database::data::UserData;
+use crate::database::data::{TimeControl, UserData};
 use crate::database::info::UserKeyInfo;
-use crate::database::{info, Database, UpdateQuery};
+use crate::database::{info, Database, RegisterError, UpdateQuery};
 use crate::prelude::*;
 use std::net::SocketAddr;
 use std::sync::Arc;
 
-use crate::match_queue::UserRegisterData;
+use crate::game_queue::LiveGames;
+use crate::match_queue::{MatchSettings, UserRegisterData};
+use crate::socket::Socket;
 use http_body_util::{combinators::BoxBody, BodyExt, Empty, Full};
 use hyper::body::{self, Bytes};
 use hyper::server::conn::http1;
@@ -16,7 +18,7 @@ use hyper_util::rt::TokioIo;
 use std::collections::HashMap;
 use tokio::net::TcpListener;
 use tokio::sync::mpsc::Sender;
-use tokio::sync::Mutex;
+use tokio::sync::RwLock;
 use url::Url;
 
 fn empty() -> BoxBody<Bytes, hyper::Error> {
@@ -31,10 +33,32 @@ fn full<T: Into<Bytes>>(chunk: T) -> BoxBody<Bytes, hyper::Error> {
         .boxed()
 }
 
+/// query string of the request as key-value pairs
+fn query_params<T>(req: &Request<T>) -> HashMap<String, String> {
+    let url =
+        Url::parse(&format!("ws://localhost:{}", &req.uri().to_string())).expect("Failed to parse");
+    url.query_pairs()
+        .map(|(k, v)| (k.into_owned(), v.into_owned()))
+        .collect()
+}
+
+/// game settings asked for in a `/connect` query, defaults for missing ones
+fn match_settings(params: &HashMap<String, String>) -> Option<MatchSettings> {
+    let mut settings = MatchSettings::default();
+    if let Some(tc) = params.get("tc") {
+        settings.time = tc.parse().ok().filter(TimeControl::is_valid)?;
+    }
+    if let Some(rules) = params.get("rules") {
+        settings.rule = rules.parse().ok()?;
+    }
+    Some(settings)
+}
+
 pub async fn run_server(
     queue_sender: Sender<crate::match_queue::UserRegisterData>,
     _update_sender: Sender<UpdateQuery>,
-    data: Arc<Mutex<Database>>,
+    data: Arc<RwLock<Database>>,
+    live_games: LiveGames,
 ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     log("http handle fn starts on 127.0.0.1:3000!");
 
@@ -54,9 +78,11 @@ pub async fn run_server(
         // make copy and move sender and data
         let queue_sender = queue_sender.clone();
         let data = data.clone();
+        let live_games = live_games.clone();
         let service = service_fn(move |mut req: Request<body::Incoming>| {
             let queue_sender = queue_sender.clone();
             let data = data.clone();
+            let live_games = live_games.clone();
             async move {
                 match (req.method(), req.uri().path()) {
                     (&Method::GET, "/") => Ok(Response::new(full("Hello, World!"))),
@@ -65,69 +91,138 @@ pub async fn run_server(
                     (&Method::GET, "/state") => Ok(Response::new(full("I'm fine"))),
 
                     (&Method::GET, "/getusers") => {
-                        let data = data.lock().await;
+                        let data = data.read().await;
                         let data = data.get_all_user_serizlie().unwrap();
                         Ok(Response::new(full(data)))
                     }
 
                     (&Method::GET, "/getgames") => {
-                        let data = data.lock().await;
+                        let data = data.read().await;
                         let data = data.get_all_game_serialize().unwrap();
                         Ok(Response::new(full(data)))
                     }
 
+                    // games in progress that can be watched
+                    (&Method::GET, "/livegames") => {
+                        let live_games = live_games.read().await;
+                        let mut games: Vec<info::LiveGameInfo> =
+                            live_games.values().map(|game| game.into()).collect();
+                        games.sort_by_key(|game| game.id);
+                        Ok(Response::new(full(serde_json::to_string(&games).unwrap())))
+                    }
+
                     (&Method::POST, "/register") => {
                         let body = req.collect().await.unwrap().to_bytes();
                         let body_str = String::from_utf8(body.to_vec()).unwrap();
-                        log(&format!("register {body_str}"));
                         let user_info: info::RegisterInfo =
                             serde_json::from_str(&body_str).unwrap();
-                        let mut data = data.lock().await;
-                        let key = data.register_user(user_info);
-                        let response = serde_json::to_string(&key).unwrap();
-                        Ok(Response::new(full(response)))
+                        log(&format!("register {}", user_info.id));
+                        match Database::register_user(&data, user_info).await {
+                            Ok(key) => {
+                                let response = serde_json::to_string(&key).unwrap();
+                                Ok(Response::new(full(response)))
+                            }
+                            Err(e) => {
+                                log(&format!("register fail: {e}"));
+                                let status = match e {
+                                    RegisterError::DuplicateId(_) => StatusCode::CONFLICT,
+                                    _ => StatusCode::BAD_REQUEST,
+                                };
+                                let error: info::ErrorInfo = e.into();
+                                let mut resp =
+                                    Response::new(full(serde_json::to_string(&error).unwrap()));
+                                *resp.status_mut() = status;
+                                Ok(resp)
+                            }
+                        }
                     }
 
                     (&Method::POST, "/login") => {
                         let body = req.collect().await.unwrap().to_bytes();
                         let body_str = String::from_utf8(body.to_vec()).unwrap();
-                        log(&format!("login {body_str}"));
                         let info: info::LoginInfo = serde_json::from_str(&body_str).unwrap();
-                        let data = data.lock().await;
-                        let user = data.try_login(&info);
-                        let resp = match user {
-                            Some(user) => {
-                                serde_json::to_string::<info::UserKeyInfo>(&info::UserKeyInfo {
-                                    key: user.key.clone(),
-                                })
-                                .unwrap()
+                        log(&format!("login {}", info.id));
+                        let key =
+                            Database::try_login(&data, &info)
+                                .await
+                                .unwrap_or(info::UserKeyInfo {
+                                    key: String::new(),
+                                    expiretime: String::new(),
+                                });
+                        let resp = serde_json::to_string(&key).unwrap();
+                        Ok(Response::new(full(resp)))
+                    }
+
+                    // trade a valid key for a new one
+                    (&Method::POST, "/refresh") => {
+                        let body = req.collect().await.unwrap().to_bytes();
+                        let body_str = String::from_utf8(body.to_vec()).unwrap();
+                        let key: info::UserKeyInfo = serde_json::from_str(&body_str).unwrap();
+                        let mut data = data.write().await;
+                        match data.refresh_session(&key) {
+                            Some(key) => {
+                                let resp = serde_json::to_string(&key).unwrap();
+                                Ok(Response::new(full(resp)))
                             }
                             None => {
-                                serde_json::to_string::<info::UserKeyInfo>(&info::UserKeyInfo {
-                                    key: "".to_string(),
-                                })
-                                .unwrap()
+                                let mut unauthorized = Response::new(empty());
+                                *unauthorized.status_mut() = StatusCode::UNAUTHORIZED;
+                                Ok(unauthorized)
                             }
-                        };
-                        Ok(Response::new(full(resp)))
+                        }
+                    }
+
+                    (&Method::POST, "/logout") => {
+                        let body = req.collect().await.unwrap().to_bytes();
+                        let body_str = String::from_utf8(body.to_vec()).unwrap();
+                        let info: info::LogoutInfo = serde_json::from_str(&body_str).unwrap();
+                        let mut data = data.write().await;
+                        if data.logout(&info) {
+                            Ok(Response::new(empty()))
+                        } else {
+                            let mut unauthorized = Response::new(empty());
+                            *unauthorized.status_mut() = StatusCode::UNAUTHORIZED;
+                            Ok(unauthorized)
+                        }
+                    }
+
+                    // active sessions of the user
+                    (&Method::GET, "/getsessions") => {
+                        let body = req.collect().await.unwrap().to_bytes();
+                        let body_str = String::from_utf8(body.to_vec()).unwrap();
+                        let key: info::UserKeyInfo = serde_json::from_str(&body_str).unwrap();
+                        let data = data.read().await;
+                        match data.get_sessions(&key) {
+                            Some(sessions) => {
+                                let resp = serde_json::to_string(&sessions).unwrap();
+                                Ok(Response::new(full(resp)))
+                            }
+                            None => {
+                                let mut unauthorized = Response::new(empty());
+                                *unauthorized.status_mut() = StatusCode::UNAUTHORIZED;
+                                Ok(unauthorized)
+                            }
+                        }
                     }
 
                     (&Method::GET, "/getuserinfo") => {
                         let body = req.collect().await.unwrap().to_bytes();
                         let body_str = String::from_utf8(body.to_vec()).unwrap();
-                        log(&format!("getuserinfo {body_str}"));
+                        log("getuserinfo");
                         let key: info::UserKeyInfo = serde_json::from_str(&body_str).unwrap();
-                        let data = data.lock().await;
+                        let data = data.read().await;
                         let user_data = data.get_user(&key);
                         if let Some(user_data) = user_data {
-                            let resp = serde_json::to_string(&user_data).unwrap();
+                            let user_info: info::UserInfo = user_data.into();
+                            let resp = serde_json::to_string(&user_info).unwrap();
                             Ok(Response::new(full(resp)))
                         } else {
                             let resp = serde_json::to_string(&info::UserInfo {
                                 id: String::new(),
-                                pwd: String::new(),
                                 rating: 0,
-                                key: String::new(),
+                                games: 0,
+                                provisional: false,
+                                deviation: 0,
                             })
                             .unwrap();
                             Ok(Response::new(full(resp)))
@@ -137,47 +232,202 @@ pub async fn run_server(
                     (&Method::GET, "/getgameinfo") => {
                         let body = req.collect().await.unwrap().to_bytes();
                         let body_str = String::from_utf8(body.to_vec()).unwrap();
-                        log(&format!("getgameinfo {body_str}"));
+                        log("getgameinfo");
                         let key: info::UserKeyInfo = serde_json::from_str(&body_str).unwrap();
-                        let data = data.lock().await;
+                        let data = data.read().await;
                         let games = data.get_user_game(&key);
                         let resp = serde_json::to_string(&games).unwrap();
                         Ok(Response::new(full(resp)))
                     }
 
+                    // stored game with all its details
+                    (&Method::GET, "/getgame") => {
+                        let params = query_params(&req);
+                        let id = params.get("id").and_then(|i| i.parse::<u64>().ok());
+                        let data = data.read().await;
+                        match id.and_then(|id| data.get_game(id)) {
+                            Some(game) => {
+                                let game: info::GameInfo = game.clone().into();
+                                let resp = serde_json::to_string(&game).unwrap();
+                                Ok(Response::new(full(resp)))
+                            }
+                            None => {
+                                let mut not_found = Response::new(empty());
+                                *not_found.status_mut() = StatusCode::NOT_FOUND;
+                                Ok(not_found)
+                            }
+                        }
+                    }
+
+                    // share a stored game as an svg image
+                    (&Method::GET, "/gameimage") => {
+                        let params = query_params(&req);
+                        let id = params.get("id").and_then(|i| i.parse::<u64>().ok());
+                        let data = data.read().await;
+                        let game = id
+                            .and_then(|id| data.get_game(id))
+                            .and_then(|game| game.replay().ok());
+                        match game {
+                            Some(game) => {
+                                let svg = game.to_svg(&fir_game::SvgOptions::default());
+                                let mut resp = Response::new(full(svg));
+                                resp.headers_mut().insert(
+                                    hyper::header::CONTENT_TYPE,
+                                    hyper::header::HeaderValue::from_static("image/svg+xml"),
+                                );
+                                Ok(resp)
+                            }
+                            None => {
+                                let mut not_found = Response::new(empty());
+                                *not_found.status_mut() = StatusCode::NOT_FOUND;
+                                Ok(not_found)
+                            }
+                        }
+                    }
+
+                    // share a stored game as an animated gif
+                    (&Method::GET, "/gamegif") => {
+                        let params = query_params(&req);
+                        let id = params.get("id").and_then(|i| i.parse::<u64>().ok());
+                        let mut options = fir_game::GifOptions::default();
+                        if let Some(delay) = params.get("delay").and_then(|d| d.parse().ok()) {
+                            options.delay_ms = delay;
+                        }
+                        if let Some(theme) =
+                            params.get("theme").and_then(|t| fir_game::Theme::named(t))
+                        {
+                            options.theme = theme;
+                        }
+                        let game = {
+                            let data = data.read().await;
+                            id.and_then(|id| data.get_game(id).cloned())
+                        };
+                        // encoding takes a while, keep it off the async workers
+                        let gif = match game {
+                            Some(game) => {
+                                tokio::task::spawn_blocking(move || game.to_gif(&options).ok())
+                                    .await
+                                    .unwrap()
+                            }
+                            None => None,
+                        };
+                        match gif {
+                            Some(gif) => {
+                                let mut resp = Response::new(full(gif));
+                                resp.headers_mut().insert(
+                                    hyper::header::CONTENT_TYPE,
+                                    hyper::header::HeaderValue::from_static("image/gif"),
+                                );
+                                Ok(resp)
+                            }
+                            None => {
+                                let mut not_found = Response::new(empty());
+                                *not_found.status_mut() = StatusCode::NOT_FOUND;
+                                Ok(not_found)
+                            }
+                        }
+                    }
+
+                    // download a stored game as a piskvork psq file
+                    (&Method::GET, "/getgamepsq") => {
+                        let params = query_params(&req);
+                        let id = params.get("id").and_then(|i| i.parse::<u64>().ok());
+                        let data = data.read().await;
+                        match id.and_then(|id| data.get_game(id)) {
+                            Some(game) => Ok(Response::new(full(game.to_record().to_psq()))),
+                            None => {
+                                let mut not_found = Response::new(empty());
+                                *not_found.status_mut() = StatusCode::NOT_FOUND;
+                                Ok(not_found)
+                            }
+                        }
+                    }
+
+                    // download a stored game as an sgf file
+                    (&Method::GET, "/getgamesgf") => {
+                        let params = query_params(&req);
+                        let id = params.get("id").and_then(|i| i.parse::<u64>().ok());
+                        let data = data.read().await;
+                        match id.and_then(|id| data.get_game(id)) {
+                            Some(game) => Ok(Response::new(full(game.to_sgf().to_sgf()))),
+                            None => {
+                                let mut not_found = Response::new(empty());
+                                *not_found.status_mut() = StatusCode::NOT_FOUND;
+                                Ok(not_found)
+                            }
+                        }
+                    }
+
+                    // evaluate a position and suggest moves
+                    (&Method::POST, "/analyze") => {
+                        let body = req.collect().await.unwrap().to_bytes();
+                        let body_str = String::from_utf8(body.to_vec()).unwrap();
+                        log(&format!("analyze {body_str}"));
+                        let request: info::AnalyzeInfo = match serde_json::from_str(&body_str) {
+                            Ok(request) => request,
+                            Err(e) => {
+                                let mut resp = Response::new(full(e.to_string()));
+                                *resp.status_mut() = StatusCode::BAD_REQUEST;
+                                return Ok(resp);
+                            }
+                        };
+                        // the search takes a while, keep it off the async workers
+                        let analysis =
+                            tokio::task::spawn_blocking(move || crate::analysis::analyze(request))
+                                .await
+                                .unwrap();
+                        match analysis {
+                            Ok(analysis) => {
+                                let resp = serde_json::to_string(&analysis).unwrap();
+                                Ok(Response::new(full(resp)))
+                            }
+                            Err(e) => {
+                                let mut resp = Response::new(full(e.to_string()));
+                                *resp.status_mut() = StatusCode::BAD_REQUEST;
+                                Ok(resp)
+                            }
+                        }
+                    }
+
                     // connect user with websocket request
                     // https://crates.io/crates/hyper-tungstenite
                     (&Method::GET, "/connect") => {
                         if hyper_tungstenite::is_upgrade_request(&req) {
+                            // parse url and check the key before upgrading
+                            let params = query_params(&req);
+                            let key = params.get("key").cloned().unwrap_or_default();
+                            let Some(settings) = match_settings(&params) else {
+                                let mut bad_request =
+                                    Response::new(full("Invalid time control or rules"));
+                                *bad_request.status_mut() = StatusCode::BAD_REQUEST;
+                                return Ok(bad_request);
+                            };
+
+                            let user = if key.is_empty() {
+                                UserData::anonymous()
+                            } else {
+                                let data = data.read().await;
+                                let key = UserKeyInfo {
+                                    key,
+                                    expiretime: String::new(),
+                                };
+                                match data.get_user(&key) {
+                                    Some(user) => user,
+                                    None => {
+                                        let mut unauthorized =
+                                            Response::new(full("Invalid or expired key"));
+                                        *unauthorized.status_mut() = StatusCode::UNAUTHORIZED;
+                                        return Ok(unauthorized);
+                                    }
+                                }
+                            };
+
                             let result = hyper_tungstenite::upgrade(&mut req, None);
                             if let Err(e) = result {
                                 log(&format!("fail in upgrade: {e}"));
                                 return Ok(Response::new(full("Upgrade fail")));
                             }
 
-                            // parse url and get key value
-                            let url =
-                                Url::parse(&format!("ws://localhost:{}", &req.uri().to_string()))
-                                    .expect("Failed to parse");
-                            let params: HashMap<String, String> = url
-                                .query_pairs()
-                                .map(|(k, v)| (k.into_owned(), v.into_owned()))
-                                .collect();
-                            let key = params.get("key").unwrap().clone();
-
-                            let user;
-                            if key == "" {
-                                user = UserData {
-                                    id: String::from("Anonymous"),
-                                    pwd: String::from(""),
-                                    rating: 0,
-                                    key: String::from(""),
-                                }
-                            } else {
-                                let data = data.lock().await;
-                                user = data.get_user(&UserKeyInfo { key: key }).unwrap();
-                            }
-
                             let (response, socket) = result.unwrap();
                             let socket = socket;
 
@@ -185,7 +435,7 @@ pub async fn run_server(
 
                             // send socket to user queue
                             queue_sender
-                                .send(UserRegisterData::new(user, socket))
+                                .send(UserRegisterData::new(user, settings, socket))
                                 .await
                                 .unwrap();
 
@@ -206,6 +456,63 @@ pub async fn run_server(
                         }
                     }
 
+                    // watch a game in progress with websocket request
+                    (&Method::GET, "/spectate") => {
+                        if !hyper_tungstenite::is_upgrade_request(&req) {
+                            return Ok(Response::new(full("Send me a corret header")));
+                        }
+                        let params = query_params(&req);
+                        let id = params.get("id").and_then(|i| i.parse::<u64>().ok());
+                        let live = match id {
+                            Some(id) => live_games.read().await.get(&id).cloned(),
+                            None => None,
+                        };
+                        let Some(live) = live else {
+                            let mut not_found = Response::new(empty());
+                            *not_found.status_mut() = StatusCode::NOT_FOUND;
+                            return Ok(not_found);
+                        };
+
+                        let (response, socket) = match hyper_tungstenite::upgrade(&mut req, None) {
+                            Ok(upgrade) => upgrade,
+                            Err(e) => {
+                                log(&format!("fail in upgrade: {e}"));
+                                return Ok(Response::new(full("Upgrade fail")));
+                            }
+                        };
+
+                        // hand the socket to the room once the handshake is done
+                        tokio::spawn(async move {
+                            let stream = match socket.await {
+                                Ok(stream) => stream,
+                                Err(e) => {
+                                    log(&format!("fail in websocket handshake: {e}"));
+                                    return;
+                                }
+                            };
+                            let socket = Socket::new(stream);
+                            let channel = socket.get_channel();
+                            let (tx, _) = &channel;
+                            let tx = tx.clone();
+                            tokio::spawn(socket.run());
+                            if !live.spectate(channel).await {
+                                let _ = tx.send(Stopper::Stop);
+                            }
+                        });
+
+                        // map websocket response to Response<BoxBody<..>>
+                        let mut res = Response::new(
+                            response
+                                .body()
+                                .clone()
+                                .map_err(|never| match never {})
+                                .boxed(),
+                        );
+                        *res.status_mut() = response.status();
+                        *res.headers_mut() = response.headers().clone();
+                        Ok(res)
+                    }
+
                     // default
                     _ => {
                         let mut not_found = Response::new(empty());
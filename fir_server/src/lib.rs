@@ -1,8 +1,12 @@
+mod analysis;
 mod cli;
+mod clock;
+mod config;
 mod database;
 mod game_queue;
 mod http_handler;
 mod match_queue;
+mod rating;
 mod socket;
 mod utility;
 
@@ -16,22 +20,25 @@ use tokio::spawn;
 pub async fn run() -> Result<(), Box<dyn std::error::Error + Sync + Send>> {
     log("server start!");
 
-    let db = database::DataManager::new();
+    let config = config::Config::from_env();
+    let db = database::DataManager::open(&config)?;
     let dbq = db.get_sender();
     let real_db = db.get_db();
 
-    let game = game_queue::GameQueue::new();
+    let game = game_queue::GameQueue::new(&config);
     let gameq = game.get_sender();
+    let live_games = game.get_live_games();
 
-    let mut game_match = match_queue::MatchQueue::new();
+    let mut game_match = match_queue::MatchQueue::new(config.rating);
     let matchq = game_match.get_sender();
 
     let server_handle = spawn(http_handler::run_server(
         matchq,
         dbq.clone(),
         real_db.clone(),
+        live_games.clone(),
     ));
-    let match_queue_handle = spawn(game_match.run(gameq.clone()));
+    let match_queue_handle = spawn(game_match.run(gameq.clone(), live_games));
     let game_queue_handle = spawn(game.run(dbq.clone()));
     let db_handle = spawn(db.run());
     let cli_handle = spawn(cli::run(dbq.clone(), real_db.clone()));
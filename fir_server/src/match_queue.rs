@@ -1,59 +1,186 @@
 use crate::{
-    database::data::{self, TimeControl},
-    game_queue::GameInitData,
+    database::{
+        data::{self, TimeControl},
+        info,
+    },
+    game_queue::{GameInitData, LiveGames},
     prelude::*,
+    rating::RatingSystem,
+    socket::Socket,
 };
 
-use hyper::upgrade::Upgraded;
-use hyper_tungstenite::{HyperWebsocket, WebSocketStream};
-use hyper_util::rt::TokioIo;
-use std::collections::VecDeque;
+use fir_game::RuleSet;
+
+use hyper_tungstenite::HyperWebsocket;
+use std::collections::{HashMap, VecDeque};
+use std::time::Duration;
+use tokio::sync::broadcast::{self, error::TryRecvError};
 use tokio::sync::mpsc::{channel, Receiver, Sender};
+use tokio::time::Instant;
+
+/// rating gap accepted right away
+const BASE_WINDOW: f64 = 100.0;
+/// how fast the accepted gap widens while waiting
+const WINDOW_PER_SECOND: f64 = 10.0;
+/// how often waiting players are matched again
+const MATCH_INTERVAL: Duration = Duration::from_secs(1);
+/// how often waiting players are told where they stand
+const STATUS_INTERVAL: Duration = Duration::from_secs(5);
+/// recent waits kept per pool for estimating the next ones
+const WAIT_HISTORY: usize = 20;
+
+/// what a player asks to play
+///
+/// only players asking for the same settings are paired.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct MatchSettings {
+    pub time: TimeControl,
+    pub rule: RuleSet,
+}
+
+impl Default for MatchSettings {
+    fn default() -> Self {
+        MatchSettings {
+            time: TimeControl {
+                seconds: 100,
+                fisher: 0,
+            },
+            rule: RuleSet::Freestyle,
+        }
+    }
+}
+
+/// sender to the client and receiver of its messages
+pub type SocketChannel = (
+    broadcast::Sender<Stopper<String>>,
+    broadcast::Receiver<Stopper<String>>,
+);
 
 #[derive(Debug)]
 pub struct UserRegisterData {
     pub data: data::UserData,
+    pub settings: MatchSettings,
     stream: Option<HyperWebsocket>,
-    pub open_stream: Option<WebSocketStream<TokioIo<Upgraded>>>,
+    pub channel: Option<SocketChannel>,
 }
 
 impl UserRegisterData {
-    pub fn new(data: data::UserData, stream: HyperWebsocket) -> Self {
+    pub fn new(data: data::UserData, settings: MatchSettings, stream: HyperWebsocket) -> Self {
         Self {
             data,
+            settings,
             stream: Some(stream),
-            open_stream: None,
+            channel: None,
+        }
+    }
+
+    /// player whose socket is connected already
+    #[cfg(test)]
+    pub fn connected(
+        data: data::UserData,
+        settings: MatchSettings,
+        channel: SocketChannel,
+    ) -> Self {
+        Self {
+            data,
+            settings,
+            stream: None,
+            channel: Some(channel),
         }
     }
 
-    // connect stream
-    async fn connect(&mut self) {
+    // connect stream, false if the handshake failed
+    async fn connect(&mut self) -> bool {
         let stream = self.stream.take().unwrap();
-        self.open_stream = Some(stream.await.unwrap());
+        match stream.await {
+            Ok(stream) => {
+                let socket = Socket::new(stream);
+                self.channel = Some(socket.get_channel());
+                tokio::spawn(socket.run());
+                true
+            }
+            Err(e) => {
+                log(&format!("fail in websocket handshake: {e}"));
+                false
+            }
+        }
+    }
+
+    fn send(&self, response: data::GameResponse) {
+        if let Some((tx, _)) = &self.channel {
+            let response: info::GameResponseInfo = response.into();
+            // a closed socket is noticed by `has_left`
+            let _ = tx.send(Stopper::Go(serde_json::to_string(&response).unwrap()));
+        }
+    }
+
+    /// whether the player closed the socket or asked to leave the queue
+    ///
+    /// other messages sent while waiting are dropped.
+    fn has_left(&mut self) -> bool {
+        let Some((_, rx)) = &mut self.channel else {
+            return true;
+        };
+        loop {
+            match rx.try_recv() {
+                Ok(Stopper::Go(message)) => {
+                    let cancel = serde_json::from_str::<info::GameCommandInfo>(&message)
+                        .is_ok_and(|command| command.command == "Cancel");
+                    if cancel {
+                        return true;
+                    }
+                }
+                Ok(Stopper::Stop) | Err(TryRecvError::Closed) => return true,
+                Err(TryRecvError::Lagged(_)) => (),
+                Err(TryRecvError::Empty) => return false,
+            }
+        }
     }
 
-    fn get_stream(&mut self) -> &mut WebSocketStream<TokioIo<Upgraded>> {
-        self.open_stream.as_mut().unwrap()
+    /// stop writing to the socket
+    fn close(&self) {
+        if let Some((tx, _)) = &self.channel {
+            let _ = tx.send(Stopper::Stop);
+        }
     }
 }
 
+/// player waiting for an opponent
+#[derive(Debug)]
+struct Waiting {
+    user: UserRegisterData,
+    since: Instant,
+}
+
 /// Match queue
 ///
 /// Basically, match queue matches two client into a game.
+///
+/// every [MatchSettings] has its own pool. players are paired with the
+/// closest rating within a window that widens the longer they wait. time is
+/// passed in, so pairing can be driven by a simulated clock.
 pub struct MatchQueue {
-    queue: VecDeque<UserRegisterData>,
+    pools: HashMap<MatchSettings, Vec<Waiting>>,
+    /// how long the last players of each pool waited
+    waits: HashMap<MatchSettings, VecDeque<Duration>>,
+    /// whether the longest waiter of the last pair played black
+    flip: bool,
+    rating: RatingSystem,
     sender: Sender<UserRegisterData>,
     receiver: Receiver<UserRegisterData>,
 }
 
 impl MatchQueue {
     /// Create a new match queue
-    pub fn new() -> Self {
+    pub fn new(rating: RatingSystem) -> Self {
         let (sender, receiver) = channel(100);
         Self {
-            queue: VecDeque::new(),
-            sender: sender,
-            receiver: receiver,
+            pools: HashMap::new(),
+            waits: HashMap::new(),
+            flip: false,
+            rating,
+            sender,
+            receiver,
         }
     }
 
@@ -61,35 +188,364 @@ impl MatchQueue {
         Sender::clone(&self.sender)
     }
 
-    pub async fn run(mut self, gameq: Sender<GameInitData>) {
+    /// add a player who started waiting at `now`
+    fn push(&mut self, user: UserRegisterData, now: Instant) {
+        let settings = user.settings;
+        let pool = self.pools.entry(settings).or_default();
+        pool.push(Waiting { user, since: now });
+        log(&format!(
+            "current queue size of {settings:?}: {}",
+            pool.len()
+        ));
+    }
+
+    /// drop players who disconnected or cancelled
+    fn remove_left(&mut self) {
+        for pool in self.pools.values_mut() {
+            pool.retain_mut(|waiting| {
+                if waiting.user.has_left() {
+                    log(&format!("{} left the match queue", waiting.user.data.id));
+                    waiting.user.close();
+                    false
+                } else {
+                    true
+                }
+            });
+        }
+        self.pools.retain(|_, pool| !pool.is_empty());
+    }
+
+    /// take out the next pair to play at `now`, black first
+    ///
+    /// the longest waiter plays black and white in turn.
+    fn take_match(&mut self, now: Instant) -> Option<(UserRegisterData, UserRegisterData)> {
+        let rating = self.rating;
+        let (settings, pair) = self.pools.iter_mut().find_map(|(settings, pool)| {
+            take_pair(pool, rating, now).map(|pair| (*settings, pair))
+        })?;
+        self.pools.retain(|_, pool| !pool.is_empty());
+
+        let waits = self.waits.entry(settings).or_default();
+        for waiting in [&pair.0, &pair.1] {
+            waits.push_back(now.saturating_duration_since(waiting.since));
+        }
+        while waits.len() > WAIT_HISTORY {
+            waits.pop_front();
+        }
+        self.flip = !self.flip;
+        if self.flip {
+            Some((pair.0.user, pair.1.user))
+        } else {
+            Some((pair.1.user, pair.0.user))
+        }
+    }
+
+    /// tell every waiting player where they stand at `now`
+    fn send_status(&self, now: Instant) {
+        for (settings, pool) in &self.pools {
+            let average = self.waits.get(settings).and_then(|waits| {
+                let total: Duration = waits.iter().sum();
+                (!waits.is_empty()).then(|| total / waits.len() as u32)
+            });
+            for (i, waiting) in pool.iter().enumerate() {
+                let estimated_wait = estimate_wait(pool, i, self.rating, now)
+                    .or_else(|| {
+                        average.map(|average| {
+                            average.saturating_sub(now.saturating_duration_since(waiting.since))
+                        })
+                    })
+                    .unwrap_or_default();
+                waiting
+                    .user
+                    .send(data::GameResponse::QueueStatus(data::QueueStatus {
+                        position: i + 1,
+                        waiting: pool.len(),
+                        estimated_wait,
+                    }));
+            }
+        }
+    }
+
+    /// start games for every pair that can be made at `now`
+    async fn make_matches(&mut self, now: Instant, gameq: &Sender<GameInitData>) {
+        while let Some((player1, player2)) = self.take_match(now) {
+            log(&format!(
+                "make match {} - {} ({:?})",
+                player1.data.id, player2.data.id, player1.settings
+            ));
+            let settings = player1.settings;
+            gameq
+                .send(GameInitData::new(player1, player2, settings))
+                .await
+                .unwrap();
+        }
+    }
+
+    pub async fn run(mut self, gameq: Sender<GameInitData>, games: LiveGames) {
         log("match queue start!");
 
+        let mut interval = tokio::time::interval(MATCH_INTERVAL);
+        let mut status = tokio::time::interval(STATUS_INTERVAL);
         loop {
-            let mut userdata = self.receiver.recv().await.unwrap();
-            // wait until websocket connection finished.
-            userdata.connect().await;
-            log("connect complete");
-
-            self.queue.push_back(userdata);
-            log(&format!("current queue size: {:?}", self.queue.len()));
-
-            // make match
-            if self.queue.len() >= 2 {
-                let player1 = self.queue.pop_front().unwrap();
-                let player2 = self.queue.pop_front().unwrap();
-
-                let init = GameInitData::new(
-                    player1,
-                    player2,
-                    TimeControl {
-                        seconds: 100,
-                        fisher: 0,
-                    },
-                );
-
-                super::utility::log("make match");
-                gameq.send(init).await.unwrap();
+            let mut send_status = false;
+            tokio::select! {
+                userdata = self.receiver.recv() => {
+                    let mut userdata = userdata.unwrap();
+                    // a player of a game in progress goes back to it
+                    let live = if userdata.data.is_anonymous() {
+                        None
+                    } else {
+                        let games = games.read().await;
+                        games
+                            .values()
+                            .find(|game| game.players.contains(&userdata.data.id))
+                            .cloned()
+                    };
+                    // wait until websocket connection finished.
+                    if !userdata.connect().await {
+                        continue;
+                    }
+                    log("connect complete");
+                    if let Some(live) = live {
+                        live.reconnect(userdata.data.id, userdata.channel.unwrap()).await;
+                        continue;
+                    }
+                    self.push(userdata, Instant::now());
+                    send_status = true;
+                }
+                _ = interval.tick() => (),
+                _ = status.tick() => send_status = true,
+            }
+            let now = Instant::now();
+            self.remove_left();
+            self.make_matches(now, &gameq).await;
+            if send_status {
+                self.send_status(now);
             }
         }
     }
 }
+
+/// rating gap `waiting` accepts at `now`
+///
+/// a Glicko-2 rating is only known up to its deviation, so the window
+/// starts that much wider.
+fn window(waiting: &Waiting, rating: RatingSystem, now: Instant) -> f64 {
+    let uncertainty = match rating {
+        RatingSystem::Elo => 0.0,
+        RatingSystem::Glicko2 => waiting.user.data.deviation,
+    };
+    let waited = now.saturating_duration_since(waiting.since).as_secs_f64();
+    BASE_WINDOW + uncertainty + WINDOW_PER_SECOND * waited
+}
+
+/// rating gap between two players, `None` for the same user twice
+fn gap(x: &data::UserData, y: &data::UserData) -> Option<f64> {
+    if x.is_anonymous() || y.is_anonymous() {
+        // anonymous players have no rating to compare
+        return Some(0.0);
+    }
+    if x.id == y.id {
+        return None;
+    }
+    Some((x.rating as f64 - y.rating as f64).abs())
+}
+
+/// rating gap between two players, if they may play each other at `now`
+fn distance(a: &Waiting, b: &Waiting, rating: RatingSystem, now: Instant) -> Option<f64> {
+    let gap = gap(&a.user.data, &b.user.data)?;
+    (gap <= window(a, rating, now) && gap <= window(b, rating, now)).then_some(gap)
+}
+
+/// time until the windows of `pool[i]` and its closest opponent meet
+///
+/// `None` when there is nobody it could be paired with.
+fn estimate_wait(
+    pool: &[Waiting],
+    i: usize,
+    rating: RatingSystem,
+    now: Instant,
+) -> Option<Duration> {
+    let player = &pool[i];
+    pool.iter()
+        .enumerate()
+        .filter(|&(j, _)| j != i)
+        .filter_map(|(_, other)| {
+            let gap = gap(&player.user.data, &other.user.data)?;
+            let seconds = [player, other]
+                .iter()
+                .map(|waiting| (gap - window(waiting, rating, now)).max(0.0) / WINDOW_PER_SECOND)
+                .fold(0.0, f64::max);
+            Some(seconds)
+        })
+        .min_by(f64::total_cmp)
+        .map(Duration::from_secs_f64)
+}
+
+/// take out the next pair of one pool at `now`
+///
+/// whoever waited longest is served first, with the closest opponent both
+/// windows accept.
+fn take_pair(
+    pool: &mut Vec<Waiting>,
+    rating: RatingSystem,
+    now: Instant,
+) -> Option<(Waiting, Waiting)> {
+    for i in 0..pool.len() {
+        let best = (0..pool.len())
+            .filter(|&j| j != i)
+            .filter_map(|j| Some((j, distance(&pool[i], &pool[j], rating, now)?)))
+            .min_by(|a, b| a.1.total_cmp(&b.1).then(a.0.cmp(&b.0)));
+        if let Some((j, _)) = best {
+            // remove the later index first so the earlier one stays put
+            let (first, second) = (i.min(j), i.max(j));
+            let second = pool.remove(second);
+            let first = pool.remove(first);
+            return Some((first, second));
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn waiting(id: &str, rating: u32, since: Instant) -> Waiting {
+        Waiting {
+            user: UserRegisterData {
+                data: data::UserData::new(String::from(id), String::new(), rating),
+                settings: MatchSettings::default(),
+                stream: None,
+                channel: None,
+            },
+            since,
+        }
+    }
+
+    fn ids(pair: &(Waiting, Waiting)) -> (&str, &str) {
+        (&pair.0.user.data.id, &pair.1.user.data.id)
+    }
+
+    #[test]
+    fn no_pair_outside_base_window() {
+        let start = Instant::now();
+        let mut pool = vec![waiting("a", 1000, start), waiting("b", 1150, start)];
+        assert!(take_pair(&mut pool, RatingSystem::Elo, start).is_none());
+        assert_eq!(pool.len(), 2);
+    }
+
+    #[test]
+    fn pair_forms_once_window_is_wide_enough() {
+        let start = Instant::now();
+        let mut pool = vec![waiting("a", 1000, start), waiting("b", 1150, start)];
+        // 100 + 10 per second reaches the gap of 150 after 5 seconds
+        let early = start + Duration::from_secs(4);
+        assert!(take_pair(&mut pool, RatingSystem::Elo, early).is_none());
+        let late = start + Duration::from_secs(5);
+        let pair = take_pair(&mut pool, RatingSystem::Elo, late).unwrap();
+        assert_eq!(ids(&pair), ("a", "b"));
+        assert!(pool.is_empty());
+    }
+
+    #[test]
+    fn both_windows_must_accept() {
+        let start = Instant::now();
+        let late = start + Duration::from_secs(10);
+        let mut pool = vec![waiting("a", 1000, start), waiting("b", 1150, late)];
+        assert!(take_pair(&mut pool, RatingSystem::Elo, late).is_none());
+    }
+
+    #[test]
+    fn longest_waiter_is_served_first() {
+        let start = Instant::now();
+        let later = start + Duration::from_secs(2);
+        // b and c are closer to each other, but a waited longest
+        let mut pool = vec![
+            waiting("a", 1000, start),
+            waiting("b", 1080, later),
+            waiting("c", 1085, later),
+        ];
+        let pair = take_pair(&mut pool, RatingSystem::Elo, later).unwrap();
+        assert_eq!(ids(&pair), ("a", "b"));
+        assert_eq!(pool.len(), 1);
+        assert_eq!(pool[0].user.data.id, "c");
+    }
+
+    #[test]
+    fn longest_waiter_alternates_colors() {
+        let start = Instant::now();
+        let mut queue = MatchQueue::new(RatingSystem::Elo);
+        for (i, id) in ["a", "b", "c", "d"].into_iter().enumerate() {
+            let since = start + Duration::from_secs(i as u64);
+            queue.push(waiting(id, 1000, since).user, since);
+        }
+        let now = start + Duration::from_secs(10);
+        let colors =
+            |(black, white): (UserRegisterData, UserRegisterData)| (black.data.id, white.data.id);
+
+        // a and c waited longest in their pairs
+        assert_eq!(
+            colors(queue.take_match(now).unwrap()),
+            ("a".into(), "b".into())
+        );
+        assert_eq!(
+            colors(queue.take_match(now).unwrap()),
+            ("d".into(), "c".into())
+        );
+        assert!(queue.take_match(now).is_none());
+    }
+
+    #[test]
+    fn never_paired_with_itself() {
+        let start = Instant::now();
+        let much_later = start + Duration::from_secs(600);
+        let mut pool = vec![waiting("a", 1000, start), waiting("a", 1000, start)];
+        assert!(take_pair(&mut pool, RatingSystem::Elo, much_later).is_none());
+        assert_eq!(estimate_wait(&pool, 0, RatingSystem::Elo, much_later), None);
+
+        pool.push(waiting("b", 1000, start));
+        let pair = take_pair(&mut pool, RatingSystem::Elo, much_later).unwrap();
+        assert_eq!(ids(&pair), ("a", "b"));
+        assert_eq!(pool.len(), 1);
+    }
+
+    #[test]
+    fn glicko_deviation_widens_window() {
+        let start = Instant::now();
+        let player = waiting("a", 1000, start);
+        assert_eq!(window(&player, RatingSystem::Elo, start), BASE_WINDOW);
+        assert_eq!(
+            window(&player, RatingSystem::Glicko2, start),
+            BASE_WINDOW + player.user.data.deviation
+        );
+
+        let mut pool = vec![player, waiting("b", 1300, start)];
+        assert!(take_pair(&mut pool, RatingSystem::Elo, start).is_none());
+        assert!(take_pair(&mut pool, RatingSystem::Glicko2, start).is_some());
+    }
+
+    #[test]
+    fn estimated_wait_until_windows_meet() {
+        let start = Instant::now();
+        let mut pool = vec![waiting("a", 1000, start)];
+        assert_eq!(estimate_wait(&pool, 0, RatingSystem::Elo, start), None);
+
+        pool.push(waiting("b", 1150, start + Duration::from_secs(2)));
+        let now = start + Duration::from_secs(2);
+        // a accepts 150 in 3 more seconds, b in 5
+        assert_eq!(
+            estimate_wait(&pool, 0, RatingSystem::Elo, now),
+            Some(Duration::from_secs(5))
+        );
+        assert_eq!(
+            estimate_wait(&pool, 1, RatingSystem::Elo, now),
+            Some(Duration::from_secs(5))
+        );
+        pool.push(waiting("c", 1020, now));
+        assert_eq!(
+            estimate_wait(&pool, 0, RatingSystem::Elo, now),
+            Some(Duration::ZERO)
+        );
+    }
+}
@@ -0,0 +1,75 @@
+//! Elo ratings
+//!
+//! The K-factor shrinks as a player plays more games. During the first
+//! [PROVISIONAL_GAMES] games a rating is provisional and moves fast, and an
+//! established player moves only half as much against a provisional one.
+//!
+//! The server can use [Glicko2] instead, which keeps a deviation and a
+//! volatility next to the rating.
+
+mod glicko2;
+
+pub use glicko2::{Glicko2, MAX_DEVIATION, START_VOLATILITY};
+
+/// how ratings are updated after a rated game
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RatingSystem {
+    Elo,
+    Glicko2,
+}
+
+/// days in a Glicko-2 rating period, the deviation grows for every period
+/// a player has not played
+pub const RATING_PERIOD_DAYS: f64 = 7.0;
+
+/// games a new player needs before the rating counts as established
+pub const PROVISIONAL_GAMES: u32 = 20;
+/// games after which the rating settles to the smallest K-factor
+const EXPERIENCED_GAMES: u32 = 100;
+
+/// rating and number of rated games of one player before a game
+#[derive(Debug, Clone, Copy)]
+pub struct Player {
+    pub rating: u32,
+    pub games: u32,
+}
+
+impl Player {
+    pub fn is_provisional(&self) -> bool {
+        self.games < PROVISIONAL_GAMES
+    }
+
+    fn k_factor(&self, opponent: &Player) -> f64 {
+        let k = if self.is_provisional() {
+            40.0
+        } else if self.games < EXPERIENCED_GAMES {
+            24.0
+        } else {
+            16.0
+        };
+        if !self.is_provisional() && opponent.is_provisional() {
+            k / 2.0
+        } else {
+            k
+        }
+    }
+}
+
+/// chance of `player` beating `opponent`, draws counting half
+pub fn expected_score(player: &Player, opponent: &Player) -> f64 {
+    let diff = opponent.rating as f64 - player.rating as f64;
+    1.0 / (1.0 + 10f64.powf(diff / 400.0))
+}
+
+/// new ratings of black and white, `score` is 1 for a black win, 0.5 for a
+/// draw and 0 for a white win
+pub fn elo(black: Player, white: Player, score: f64) -> (u32, u32) {
+    let update = |player: &Player, opponent: &Player, score: f64| {
+        let change = player.k_factor(opponent) * (score - expected_score(player, opponent));
+        (player.rating as f64 + change).round().max(0.0) as u32
+    };
+    (
+        update(&black, &white, score),
+        update(&white, &black, 1.0 - score),
+    )
+}
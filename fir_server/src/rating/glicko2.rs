@@ -0,0 +1,164 @@
+//! Glicko-2 ratings
+//!
+//! Follows Glickman's "Example of the Glicko-2 system". Every rated game is
+//! a rating period of its own, and the deviation also grows with the time
+//! a player has not played.
+
+use std::f64::consts::PI;
+
+/// ratio between the Glicko and the Glicko-2 scale
+const SCALE: f64 = 173.7178;
+/// rating the Glicko-2 scale is centered on
+const CENTER: f64 = 1500.0;
+/// how much the volatility may change, between 0.3 and 1.2
+const TAU: f64 = 0.5;
+/// convergence tolerance of the volatility iteration
+const EPSILON: f64 = 0.000001;
+
+/// deviation of a new player, and the largest deviation
+pub const MAX_DEVIATION: f64 = 350.0;
+/// volatility of a new player
+pub const START_VOLATILITY: f64 = 0.06;
+
+/// a player on the Glicko scale
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Glicko2 {
+    pub rating: f64,
+    pub deviation: f64,
+    pub volatility: f64,
+}
+
+impl Glicko2 {
+    fn mu(&self) -> f64 {
+        (self.rating - CENTER) / SCALE
+    }
+
+    fn phi(&self) -> f64 {
+        self.deviation / SCALE
+    }
+
+    /// deviation after `periods` rating periods without games
+    pub fn decay(self, periods: f64) -> Self {
+        let phi = (self.phi().powi(2) + periods.max(0.0) * self.volatility.powi(2)).sqrt();
+        Glicko2 {
+            deviation: (phi * SCALE).min(MAX_DEVIATION),
+            ..self
+        }
+    }
+
+    /// rating after one period with `games`, each an opponent and a score
+    /// of 1 for a win, 0.5 for a draw and 0 for a loss
+    pub fn update(self, games: &[(Glicko2, f64)]) -> Self {
+        if games.is_empty() {
+            return self.decay(1.0);
+        }
+        let (mu, phi) = (self.mu(), self.phi());
+
+        let mut v_inv = 0.0;
+        let mut sum = 0.0;
+        for (opponent, score) in games {
+            let g = g(opponent.phi());
+            let e = expected(mu, opponent.mu(), g);
+            v_inv += g * g * e * (1.0 - e);
+            sum += g * (score - e);
+        }
+        let v = 1.0 / v_inv;
+        let delta = v * sum;
+
+        let sigma = self.new_volatility(phi, v, delta);
+        let phi_star = (phi * phi + sigma * sigma).sqrt();
+        let phi = 1.0 / (1.0 / (phi_star * phi_star) + 1.0 / v).sqrt();
+        let mu = mu + phi * phi * sum;
+
+        Glicko2 {
+            rating: mu * SCALE + CENTER,
+            deviation: (phi * SCALE).min(MAX_DEVIATION),
+            volatility: sigma,
+        }
+    }
+
+    /// step 5 of the example, the Illinois variant of regula falsi
+    fn new_volatility(&self, phi: f64, v: f64, delta: f64) -> f64 {
+        let a = (self.volatility * self.volatility).ln();
+        let f = |x: f64| {
+            let ex = x.exp();
+            let d = phi * phi + v + ex;
+            ex * (delta * delta - phi * phi - v - ex) / (2.0 * d * d) - (x - a) / (TAU * TAU)
+        };
+
+        let mut big_a = a;
+        let mut big_b = if delta * delta > phi * phi + v {
+            (delta * delta - phi * phi - v).ln()
+        } else {
+            let mut k = 1.0;
+            while f(a - k * TAU) < 0.0 {
+                k += 1.0;
+            }
+            a - k * TAU
+        };
+        let (mut f_a, mut f_b) = (f(big_a), f(big_b));
+        while (big_b - big_a).abs() > EPSILON {
+            let big_c = big_a + (big_a - big_b) * f_a / (f_b - f_a);
+            let f_c = f(big_c);
+            if f_c * f_b <= 0.0 {
+                big_a = big_b;
+                f_a = f_b;
+            } else {
+                f_a /= 2.0;
+            }
+            big_b = big_c;
+            f_b = f_c;
+        }
+        (big_a / 2.0).exp()
+    }
+}
+
+fn g(phi: f64) -> f64 {
+    1.0 / (1.0 + 3.0 * phi * phi / (PI * PI)).sqrt()
+}
+
+fn expected(mu: f64, opponent_mu: f64, g: f64) -> f64 {
+    1.0 / (1.0 + (-g * (mu - opponent_mu)).exp())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn player(rating: f64, deviation: f64) -> Glicko2 {
+        Glicko2 {
+            rating,
+            deviation,
+            volatility: START_VOLATILITY,
+        }
+    }
+
+    #[test]
+    fn glickman_example() {
+        let games = [
+            (player(1400.0, 30.0), 1.0),
+            (player(1550.0, 100.0), 0.0),
+            (player(1700.0, 300.0), 0.0),
+        ];
+        let after = player(1500.0, 200.0).update(&games);
+        assert!((after.rating - 1464.05).abs() < 0.1, "{after:?}");
+        assert!((after.deviation - 151.52).abs() < 0.01, "{after:?}");
+        assert!((after.volatility - 0.05999).abs() < 0.00001, "{after:?}");
+    }
+
+    #[test]
+    fn no_games_only_widens_deviation() {
+        let before = player(1500.0, 200.0);
+        let after = before.update(&[]);
+        assert_eq!(after.rating, before.rating);
+        assert_eq!(after.volatility, before.volatility);
+        // sqrt(200^2 + (0.06 * 173.7178)^2)
+        assert!((after.deviation - 200.27).abs() < 0.01, "{after:?}");
+    }
+
+    #[test]
+    fn decay_stops_at_the_largest_deviation() {
+        assert_eq!(player(1500.0, 340.0).decay(1000.0).deviation, MAX_DEVIATION);
+        assert_eq!(player(1500.0, 200.0).decay(-1.0).deviation, 200.0);
+    }
+}
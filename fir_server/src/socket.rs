@@ -42,12 +42,12 @@ impl Socket {
                 let message = self.read.next().await;
                 if let Some(ref message) = message {
                     match message {
-                        Ok(message) => match message {
-                            Message::Text(msg) => {
-                                tx.send(Stopper::Go(String::from(msg))).unwrap();
+                        Ok(message) => {
+                            if let Message::Text(msg) = message {
+                                // nobody listens once the game is over
+                                let _ = tx.send(Stopper::Go(String::from(msg)));
                             }
-                            _ => (),
-                        },
+                        }
                         Err(e) => {
                             log(format!("Error: {e}").as_str());
                         }
@@ -57,14 +57,16 @@ impl Socket {
                 }
             }
 
-            self.tx_out.send(Stopper::Stop).unwrap();
+            let _ = self.tx_out.send(Stopper::Stop);
         });
 
         let h2 = tokio::spawn(async move {
             while let Ok(message) = self.rx_in.recv().await {
                 if let Stopper::Go(ref message) = message {
-                    if true {
-                        self.write.send(Message::text(message)).await.unwrap();
+                    if let Err(e) = self.write.send(Message::text(message)).await {
+                        // the reading half reports the closed connection
+                        log(&format!("Error: {e}"));
+                        break;
                     }
                 } else {
                     break;
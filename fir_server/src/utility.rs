@@ -10,7 +10,7 @@ pub fn log(message: &str) {
 /// you can also use [Option] for this.
 ///
 /// # expample
-/// ```
+/// ```ignore
 /// while let Some(e) = receiver.recv() {
 ///     if let Stop = e {
 ///         break;